@@ -0,0 +1,210 @@
+use std::{collections::HashMap, fs};
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    chunk::MAX_LIGHT,
+    voxel::{Face, Voxel, LIQUID_LEVEL_FULL, VOXEL_SIZE},
+};
+
+pub const BLOCKS_PATH: &str = "assets/blocks.ron";
+/// Copy of `BLOCKS_PATH` built into the binary, used when the file can't be loaded.
+const BUNDLED_BLOCKS: &str = include_str!("../assets/blocks.ron");
+
+pub struct BlockPlugin;
+
+impl Plugin for BlockPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BlockRegistry>()
+            .add_systems(PreStartup, load_blocks);
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+pub struct BlockId(pub u16);
+
+impl BlockId {
+    // Blocks the terrain generator places, which `blocks.ron` has to define under these ids.
+    pub const STONE: BlockId = BlockId(0);
+    pub const WATER: BlockId = BlockId(4);
+    // Other bundled blocks, which only the tests refer to by id.
+    #[cfg(test)]
+    pub const GLASS: BlockId = BlockId(3);
+    #[cfg(test)]
+    pub const GLOWSTONE: BlockId = BlockId(5);
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct BlockDef {
+    pub id: BlockId,
+    pub name: String,
+    /// Hides the faces of neighboring voxels.
+    pub opaque: bool,
+    /// Drawn in the alpha-blended mesh pass.
+    #[serde(default)]
+    pub transparent: bool,
+    /// Lowers its top according to the voxel's fill level.
+    #[serde(default)]
+    pub liquid: bool,
+    /// Block light the block gives off, up to `MAX_LIGHT`.
+    #[serde(default)]
+    pub light: u8,
+    pub atlas: AtlasCells,
+}
+
+/// Texture atlas cells, as (column, row), drawn on each face of a block.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AtlasCells {
+    pub top: (u16, u16),
+    /// All four sides.
+    pub side: (u16, u16),
+    pub bottom: (u16, u16),
+}
+
+/// Block behavior by id. Ids missing from the registry act as plain opaque blocks.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct BlockRegistry {
+    blocks: HashMap<BlockId, BlockDef>,
+}
+
+impl BlockRegistry {
+    pub fn from_ron(contents: &str) -> Result<Self, ron::error::SpannedError> {
+        let defs: Vec<BlockDef> = ron::from_str(contents)?;
+        Ok(Self {
+            blocks: defs.into_iter().map(|def| (def.id, def)).collect(),
+        })
+    }
+
+    /// The blocks from the copy of `blocks.ron` built into the binary.
+    pub fn bundled() -> Self {
+        Self::from_ron(BUNDLED_BLOCKS).expect("bundled blocks.ron should parse")
+    }
+
+    pub fn get(&self, id: BlockId) -> Option<&BlockDef> {
+        self.blocks.get(&id)
+    }
+
+    pub fn is_opaque(&self, voxel: &Voxel) -> bool {
+        self.get(voxel.block).is_none_or(|def| def.opaque)
+    }
+
+    pub fn is_transparent(&self, voxel: &Voxel) -> bool {
+        self.get(voxel.block).is_some_and(|def| def.transparent)
+    }
+
+    pub fn is_liquid(&self, voxel: &Voxel) -> bool {
+        self.get(voxel.block).is_some_and(|def| def.liquid)
+    }
+
+    pub fn emission(&self, voxel: &Voxel) -> u8 {
+        self.get(voxel.block)
+            .map_or(0, |def| def.light.min(MAX_LIGHT))
+    }
+
+    /// Atlas cell drawn on `face` of the block, the first cell for ids missing from the registry.
+    // Chunks are still drawn untextured, so only the tests look cells up so far.
+    #[allow(dead_code)]
+    pub fn atlas_cell(&self, id: BlockId, face: Face) -> (u16, u16) {
+        let atlas = self.get(id).map(|def| def.atlas).unwrap_or_default();
+        match face {
+            Face::Top => atlas.top,
+            Face::Bottom => atlas.bottom,
+            _ => atlas.side,
+        }
+    }
+
+    /// How far the top of the voxel sits below a full block.
+    pub fn top_drop(&self, voxel: &Voxel) -> f32 {
+        if !self.is_liquid(voxel) {
+            return 0.0;
+        }
+
+        (LIQUID_LEVEL_FULL - voxel.level.min(LIQUID_LEVEL_FULL)) as f32 / 8.0 * VOXEL_SIZE
+    }
+}
+
+fn load_blocks(mut registry: ResMut<BlockRegistry>) {
+    let contents = match fs::read_to_string(BLOCKS_PATH) {
+        Ok(contents) => contents,
+        Err(err) => {
+            error!("Failed to read {BLOCKS_PATH}, using the bundled blocks: {err}");
+            *registry = BlockRegistry::bundled();
+            return;
+        }
+    };
+
+    *registry = BlockRegistry::from_ron(&contents).unwrap_or_else(|err| {
+        error!("Invalid {BLOCKS_PATH}, using the bundled blocks: {err}");
+        BlockRegistry::bundled()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_loads_from_ron() {
+        let registry = BlockRegistry::from_ron(
+            r#"[
+                (
+                    id: 7,
+                    name: "Ice",
+                    opaque: false,
+                    transparent: true,
+                    atlas: (top: (3, 1), side: (4, 1), bottom: (3, 1)),
+                ),
+                (
+                    id: 8,
+                    name: "Lamp",
+                    opaque: true,
+                    light: 20,
+                    atlas: (top: (5, 1), side: (5, 1), bottom: (5, 1)),
+                ),
+            ]"#,
+        )
+        .unwrap();
+        let ice = Voxel {
+            block: BlockId(7),
+            level: 0,
+        };
+        let lamp = Voxel {
+            block: BlockId(8),
+            level: 0,
+        };
+        let unknown = Voxel {
+            block: BlockId(9),
+            level: 0,
+        };
+
+        assert_eq!(registry.get(BlockId(7)).unwrap().name, "Ice");
+        assert!(!registry.is_opaque(&ice));
+        assert!(registry.is_transparent(&ice));
+        assert!(!registry.is_liquid(&ice));
+        assert!(registry.is_opaque(&lamp));
+        assert!(!registry.is_transparent(&lamp));
+        assert_eq!(registry.emission(&lamp), MAX_LIGHT);
+        assert!(registry.is_opaque(&unknown));
+        assert!(!registry.is_transparent(&unknown));
+        assert_eq!(registry.atlas_cell(BlockId(7), Face::Top), (3, 1));
+        assert_eq!(registry.atlas_cell(BlockId(7), Face::Left), (4, 1));
+        assert_eq!(registry.atlas_cell(BlockId(7), Face::Bottom), (3, 1));
+        assert_eq!(registry.atlas_cell(BlockId(8), Face::Front), (5, 1));
+        assert_eq!(registry.atlas_cell(BlockId(9), Face::Top), (0, 0));
+    }
+
+    #[test]
+    fn bundled_blocks_parse() {
+        let registry = BlockRegistry::bundled();
+        for id in [
+            BlockId::STONE,
+            BlockId::GLASS,
+            BlockId::WATER,
+            BlockId::GLOWSTONE,
+        ] {
+            assert!(registry.get(id).is_some());
+        }
+    }
+}
@@ -1,279 +1,621 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, mem, sync::Arc};
 
 use bevy::{
     prelude::*,
     render::{mesh::Indices, render_resource::PrimitiveTopology},
 };
-use noise::{NoiseFn, Perlin};
 
-use crate::voxel::{Voxel, VOXEL_SIZE};
+use crate::{
+    block::{BlockId, BlockRegistry},
+    generation::{surface_heights, TerrainNoise},
+    voxel::{Face, Voxel, LIQUID_LEVEL_FULL, VOXEL_SIZE},
+};
 
 pub const CHUNK_SIZE: usize = 32;
 pub const CHUNK_SIZE_PADDED: usize = 34;
 pub const SEA_LEVEL: isize = 32;
+pub const SURFACE_AMPLITUDE: f64 = 100.0;
+pub const AO_BRIGHTNESS: [f32; 4] = [0.25, 0.5, 0.75, 1.0];
+pub const MAX_LIGHT: u8 = 15;
 
 #[derive(Component, Clone, Debug)]
 pub struct Chunk {
-    pub voxel_map: HashMap<[isize; 3], Voxel>,
+    /// Shared so mesh tasks can take cheap snapshots; edit through `Arc::make_mut`.
+    pub storage: Arc<ChunkStorage>,
     pub chunk_x: isize,
     pub chunk_y: isize,
     pub chunk_z: isize,
     pub entity: Option<Entity>,
+    /// World Y of the highest opaque voxel in each padded column, counting chunks that aren't
+    /// loaded yet as generated. Voxels above it see the sky.
+    pub column_tops: Vec<isize>,
+    /// Skylight of every padded voxel. Like `block_light`, `Terrain` keeps it up to date across
+    /// chunk borders as voxels change.
+    pub skylight: Skylight,
+    /// Block light of every lit padded voxel, by local position. `Terrain` keeps it up to date
+    /// across chunk borders as voxels change.
+    pub block_light: HashMap<[isize; 3], u8>,
+    /// Set when the voxels change so the chunk gets remeshed.
+    pub dirty: bool,
+}
+
+#[derive(Clone, Debug)]
+pub enum ChunkStorage {
+    Uniform(Option<Voxel>),
+    Sparse(HashMap<[isize; 3], Voxel>),
+}
+
+/// Skylight (0 to `MAX_LIGHT`) of every padded voxel, `Uniform` while they all share a level.
+#[derive(Clone, Debug)]
+pub enum Skylight {
+    Uniform(u8),
+    Dense(Vec<u8>),
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct MeshingSettings {
+    pub greedy: bool,
+    pub ao_enabled: bool,
+    pub skylight_enabled: bool,
+    /// Draws chunks unlit and without AO or light, to show bare geometry.
+    pub flat_lighting: bool,
+}
+
+impl Default for MeshingSettings {
+    fn default() -> Self {
+        Self {
+            greedy: false,
+            ao_enabled: true,
+            skylight_enabled: true,
+            flat_lighting: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeshPass {
+    Opaque,
+    Transparent,
 }
 
+/// Corner indices of a quad's two triangles, split along the 0-2 diagonal or the 1-3 one.
+const QUAD_TRIANGLES: [[u32; 6]; 2] = [[0, 1, 2, 0, 2, 3], [1, 2, 3, 1, 3, 0]];
+
 #[derive(Clone, Copy, Debug, Default)]
 struct Vertex {
     position: [f32; 3],
     normal: [f32; 3],
+    color: [f32; 4],
 }
 
-impl Chunk {
-    pub fn new(seed: u32, chunk_x: isize, chunk_y: isize, chunk_z: isize) -> Self {
-        let perlin = Perlin::new(seed);
+#[derive(Clone, Copy, Debug)]
+struct Quad {
+    face: Face,
+    voxel: Voxel,
+    positions: [[f32; 3]; 4],
+    ao: [u8; 4],
+    light: u8,
+}
 
-        let mut voxel_map = HashMap::new();
+impl Chunk {
+    pub fn new(noise: &TerrainNoise, chunk_x: isize, chunk_y: isize, chunk_z: isize) -> Self {
+        let mut storage = ChunkStorage::default();
+        let mut skylight = Skylight::Uniform(0);
+        let half = CHUNK_SIZE_PADDED as isize / 2;
+        let heights = surface_heights(
+            noise,
+            chunk_x * CHUNK_SIZE as isize - half,
+            chunk_z * CHUNK_SIZE as isize - half,
+            CHUNK_SIZE_PADDED,
+            CHUNK_SIZE_PADDED,
+        );
         for x in -(CHUNK_SIZE_PADDED as isize / 2)..CHUNK_SIZE_PADDED as isize / 2 {
-            let noise_x = (x as f64 + chunk_x as f64 * CHUNK_SIZE as f64) * 0.01;
             for z in -(CHUNK_SIZE_PADDED as isize / 2)..CHUNK_SIZE_PADDED as isize / 2 {
-                let noise_z = (z as f64 + chunk_z as f64 * CHUNK_SIZE as f64) * 0.01;
-
-                let max_y = SEA_LEVEL + (perlin.get([noise_x, noise_z]) * 100.0).round() as isize;
+                let max_y = heights[Self::column_index(x, z)];
                 for y in -(CHUNK_SIZE_PADDED as isize / 2)..CHUNK_SIZE_PADDED as isize / 2 {
                     let world_y = y + chunk_y * CHUNK_SIZE as isize;
                     if world_y <= max_y {
-                        let new_voxel = Voxel::default();
-                        voxel_map.insert([x, y, z], new_voxel);
+                        let new_voxel = Voxel {
+                            block: BlockId::STONE,
+                            level: 0,
+                        };
+                        storage.set([x, y, z], Some(new_voxel));
+                    } else if noise.water_level.is_some_and(|level| world_y <= level) {
+                        let water = Voxel {
+                            block: BlockId::WATER,
+                            level: LIQUID_LEVEL_FULL,
+                        };
+                        storage.set([x, y, z], Some(water));
+                    }
+
+                    // Everything above the surface is open to the sky, and nothing below it is.
+                    if world_y > max_y {
+                        skylight.set([x, y, z], MAX_LIGHT);
                     }
                 }
             }
         }
 
+        storage.compact();
+        skylight.compact();
+
         Self {
-            voxel_map,
+            storage: Arc::new(storage),
             chunk_x,
             chunk_y,
             chunk_z,
             entity: None,
+            column_tops: heights,
+            skylight,
+            block_light: HashMap::new(),
+            dirty: false,
+        }
+    }
+
+    /// Index into `column_tops` of the padded column at local `x`, `z`.
+    pub fn column_index(x: isize, z: isize) -> usize {
+        let half = CHUNK_SIZE_PADDED as isize / 2;
+        ((z + half) * CHUNK_SIZE_PADDED as isize + x + half) as usize
+    }
+
+    fn padded_index([x, y, z]: [isize; 3]) -> usize {
+        let half = CHUNK_SIZE_PADDED as isize / 2;
+        (((x + half) * CHUNK_SIZE_PADDED as isize + y + half) * CHUNK_SIZE_PADDED as isize
+            + z
+            + half) as usize
+    }
+
+    pub fn coords_at(translation: Vec3) -> [isize; 3] {
+        let voxel = (translation / VOXEL_SIZE)
+            .round()
+            .to_array()
+            .map(|c| c as isize);
+        Self::voxel_coords(voxel).0
+    }
+
+    /// Splits a world voxel position into chunk coordinates and a position local to that chunk.
+    pub fn voxel_coords(world: [isize; 3]) -> ([isize; 3], [isize; 3]) {
+        let half = CHUNK_SIZE as isize / 2;
+        (
+            world.map(|w| (w + half).div_euclid(CHUNK_SIZE as isize)),
+            world.map(|w| (w + half).rem_euclid(CHUNK_SIZE as isize) - half),
+        )
+    }
+
+    /// Counts each voxel type in the unpadded chunk, with `None` standing for air.
+    pub fn histogram(&self) -> HashMap<Option<Voxel>, u32> {
+        let mut histogram = HashMap::new();
+        for x in -(CHUNK_SIZE as isize / 2)..CHUNK_SIZE as isize / 2 {
+            for y in -(CHUNK_SIZE as isize / 2)..CHUNK_SIZE as isize / 2 {
+                for z in -(CHUNK_SIZE as isize / 2)..CHUNK_SIZE as isize / 2 {
+                    *histogram
+                        .entry(self.storage.get(&[x, y, z]).copied())
+                        .or_insert(0) += 1;
+                }
+            }
         }
+        histogram
     }
 
-    pub fn to_mesh(&self) -> Mesh {
+    /// Approximate heap and inline usage, counting one control byte per map slot.
+    pub fn memory_bytes(&self) -> usize {
+        mem::size_of::<Self>()
+            + mem::size_of::<ChunkStorage>()
+            + self.storage.memory_bytes()
+            + self.column_tops.capacity() * mem::size_of::<isize>()
+            + self.skylight.memory_bytes()
+            + self.block_light.capacity() * (mem::size_of::<([isize; 3], u8)>() + 1)
+    }
+
+    /// Light (0 to `MAX_LIGHT`) for every padded voxel, the brighter of skylight and block light.
+    pub fn light(&self, settings: &MeshingSettings) -> Vec<u8> {
+        let mut light = if settings.skylight_enabled {
+            self.skylight.levels()
+        } else {
+            vec![MAX_LIGHT; CHUNK_SIZE_PADDED.pow(3)]
+        };
+        for (&pos, &block_light) in &self.block_light {
+            let light = &mut light[Self::padded_index(pos)];
+            *light = (*light).max(block_light);
+        }
+        light
+    }
+
+    /// Builds the mesh for `pass`, shaded with `light` from `Chunk::light`.
+    pub fn to_mesh(
+        &self,
+        settings: &MeshingSettings,
+        pass: MeshPass,
+        registry: &BlockRegistry,
+        light: &[u8],
+    ) -> Mesh {
+        let mut quads: Vec<Quad> = Vec::new();
+        if settings.greedy {
+            for face in Face::ALL {
+                self.greedy_quads(face, pass, settings, registry, light, &mut quads);
+            }
+        } else {
+            for x in -(CHUNK_SIZE_PADDED as isize / 2)..CHUNK_SIZE_PADDED as isize / 2 {
+                for y in -(CHUNK_SIZE_PADDED as isize / 2)..CHUNK_SIZE_PADDED as isize / 2 {
+                    for z in -(CHUNK_SIZE_PADDED as isize / 2)..CHUNK_SIZE_PADDED as isize / 2 {
+                        if x.min(y.min(z)) == -(CHUNK_SIZE_PADDED as isize / 2)
+                            || x.max(y.max(z)) == CHUNK_SIZE_PADDED as isize / 2 - 1
+                        {
+                            continue;
+                        }
+
+                        for face in Face::ALL {
+                            if let Some(voxel) = self.visible_voxel([x, y, z], face, pass, registry)
+                            {
+                                quads.push(Quad {
+                                    face,
+                                    voxel,
+                                    positions: face.positions([x, y, z]),
+                                    ao: self.face_ao([x, y, z], face, settings, registry),
+                                    light: Self::face_light(light, [x, y, z], face),
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
         let mut vertices: Vec<Vertex> = Vec::new();
         let mut indices: Vec<u32> = Vec::new();
         let mut vertex_count = 0u32;
 
-        for x in -(CHUNK_SIZE_PADDED as isize / 2)..CHUNK_SIZE_PADDED as isize / 2 {
-            let neg_x = x as f32 - VOXEL_SIZE * 0.5;
-            let pos_x = x as f32 + VOXEL_SIZE * 0.5;
+        for mut quad in quads {
+            // Partly filled liquids lower the top edge of the quad.
+            let drop = registry.top_drop(&quad.voxel);
+            if drop > 0.0 {
+                let min_y = quad.positions.iter().map(|p| p[1]).fold(f32::MAX, f32::min);
+                for position in &mut quad.positions {
+                    if quad.face == Face::Top || position[1] > min_y {
+                        position[1] -= drop;
+                    }
+                }
+            }
 
-            for y in -(CHUNK_SIZE_PADDED as isize / 2)..CHUNK_SIZE_PADDED as isize / 2 {
-                let neg_y = y as f32 - VOXEL_SIZE * 0.5;
-                let pos_y = y as f32 + VOXEL_SIZE * 0.5;
+            let normal = quad.face.normal();
+            let light = 0.8f32.powi((MAX_LIGHT - quad.light) as i32);
+            for (position, ao) in quad.positions.into_iter().zip(quad.ao) {
+                let brightness = AO_BRIGHTNESS[ao as usize] * light;
+                vertices.push(Vertex {
+                    position,
+                    normal,
+                    color: [brightness, brightness, brightness, 1.0],
+                });
+            }
 
-                for z in -(CHUNK_SIZE_PADDED as isize / 2)..CHUNK_SIZE_PADDED as isize / 2 {
-                    let neg_z = z as f32 - VOXEL_SIZE * 0.5;
-                    let pos_z = z as f32 + VOXEL_SIZE * 0.5;
+            // Split along the brighter diagonal so AO interpolates evenly across the quad.
+            let [a0, a1, a2, a3] = quad.ao;
+            let split = QUAD_TRIANGLES[(a0 + a2 < a1 + a3) as usize];
+            indices.extend(split.map(|corner| vertex_count + corner));
+            vertex_count += 4;
+        }
 
-                    if self.voxel_map.get(&[x, y, z]).is_none()
-                        || x.min(y.min(z)) == -(CHUNK_SIZE_PADDED as isize / 2)
-                        || x.max(y.max(z)) == CHUNK_SIZE_PADDED as isize / 2 - 1
-                    {
+        let positions = vertices.iter().map(|v| v.position).collect::<Vec<_>>();
+        let normals = vertices.iter().map(|v| v.normal).collect::<Vec<_>>();
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+            .with_indices(Some(Indices::U32(indices)));
+        if (settings.ao_enabled || settings.skylight_enabled) && !settings.flat_lighting {
+            let colors = vertices.iter().map(|v| v.color).collect::<Vec<_>>();
+            mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+        }
+        mesh
+    }
+
+    /// Light reaching `face` of `pos`, taken from the voxel in front of it.
+    fn face_light(light: &[u8], pos: [isize; 3], face: Face) -> u8 {
+        let [dx, dy, dz] = face.offset();
+        light[Self::padded_index([pos[0] + dx, pos[1] + dy, pos[2] + dz])]
+    }
+
+    /// Per-corner occlusion level (0 darkest, 3 unoccluded) for the quad on `face` of `pos`,
+    /// from the two edge voxels and one corner voxel in front of each vertex.
+    fn face_ao(
+        &self,
+        pos: [isize; 3],
+        face: Face,
+        settings: &MeshingSettings,
+        registry: &BlockRegistry,
+    ) -> [u8; 4] {
+        if !settings.ao_enabled {
+            return [3; 4];
+        }
+
+        let w_axis = face.axis();
+        let u_axis = (w_axis + 1) % 3;
+        let v_axis = (w_axis + 2) % 3;
+        let mut front = pos;
+        front[w_axis] += face.sign();
+
+        face.positions(pos).map(|corner| {
+            let step = |axis: usize| {
+                if corner[axis] > pos[axis] as f32 * VOXEL_SIZE {
+                    1
+                } else {
+                    -1
+                }
+            };
+            let mut side_u = front;
+            side_u[u_axis] += step(u_axis);
+            let mut side_v = front;
+            side_v[v_axis] += step(v_axis);
+            let mut diagonal = side_u;
+            diagonal[v_axis] += step(v_axis);
+
+            let solid = |p: [isize; 3]| {
+                self.storage
+                    .get(&p)
+                    .is_some_and(|voxel| registry.is_opaque(voxel)) as u8
+            };
+            let (side_u, side_v, diagonal) = (solid(side_u), solid(side_v), solid(diagonal));
+            if side_u == 1 && side_v == 1 {
+                0
+            } else {
+                3 - (side_u + side_v + diagonal)
+            }
+        })
+    }
+
+    /// The voxel at `pos`, if it belongs to `pass` and its `face` isn't hidden by its neighbor.
+    /// Opaque faces show against anything non-opaque; transparent faces show against anything
+    /// but the same block. A liquid's sides also show against a lower level of itself, whose
+    /// surface leaves a strip of them exposed.
+    fn visible_voxel(
+        &self,
+        pos: [isize; 3],
+        face: Face,
+        pass: MeshPass,
+        registry: &BlockRegistry,
+    ) -> Option<Voxel> {
+        let voxel = *self.storage.get(&pos)?;
+        if registry.is_transparent(&voxel) != (pass == MeshPass::Transparent) {
+            return None;
+        }
+
+        let [dx, dy, dz] = face.offset();
+        let visible = match self.storage.get(&[pos[0] + dx, pos[1] + dy, pos[2] + dz]) {
+            None => true,
+            Some(neighbor) if registry.is_liquid(&voxel) => {
+                neighbor.block != voxel.block || (face.axis() != 1 && neighbor.level < voxel.level)
+            }
+            Some(neighbor) if registry.is_transparent(&voxel) => neighbor.block != voxel.block,
+            Some(neighbor) => !registry.is_opaque(neighbor),
+        };
+        visible.then_some(voxel)
+    }
+
+    /// Sweeps each slice of the chunk facing `face`, merging runs of matching visible voxels
+    /// into rectangles. `u` and `v` are the two axes spanning the slice, `w` the one it steps along.
+    fn greedy_quads(
+        &self,
+        face: Face,
+        pass: MeshPass,
+        settings: &MeshingSettings,
+        registry: &BlockRegistry,
+        light: &[u8],
+        quads: &mut Vec<Quad>,
+    ) {
+        let half = CHUNK_SIZE as isize / 2;
+        let w_axis = face.axis();
+        let u_axis = (w_axis + 1) % 3;
+        let v_axis = (w_axis + 2) % 3;
+
+        let voxel_pos = |w: isize, u: usize, v: usize| {
+            let mut pos = [0; 3];
+            pos[w_axis] = w;
+            pos[u_axis] = u as isize - half;
+            pos[v_axis] = v as isize - half;
+            pos
+        };
+
+        for w in -half..half {
+            let mut mask = vec![None; CHUNK_SIZE * CHUNK_SIZE];
+            for u in 0..CHUNK_SIZE {
+                for v in 0..CHUNK_SIZE {
+                    let pos = voxel_pos(w, u, v);
+                    mask[u * CHUNK_SIZE + v] =
+                        self.visible_voxel(pos, face, pass, registry).map(|voxel| {
+                            (
+                                voxel,
+                                self.face_ao(pos, face, settings, registry),
+                                Self::face_light(light, pos, face),
+                            )
+                        });
+                }
+            }
+
+            for u in 0..CHUNK_SIZE {
+                let mut v = 0;
+                while v < CHUNK_SIZE {
+                    let Some(cell) = mask[u * CHUNK_SIZE + v] else {
+                        v += 1;
                         continue;
-                    }
+                    };
 
-                    if self.voxel_map.get(&[x - 1, y, z]).is_none() {
-                        vertices.extend(&[
-                            Vertex {
-                                position: [neg_x, neg_y, neg_z],
-                                normal: [-1.0, 0.0, 0.0],
-                            },
-                            Vertex {
-                                position: [neg_x, neg_y, pos_z],
-                                normal: [-1.0, 0.0, 0.0],
-                            },
-                            Vertex {
-                                position: [neg_x, pos_y, pos_z],
-                                normal: [-1.0, 0.0, 0.0],
-                            },
-                            Vertex {
-                                position: [neg_x, pos_y, neg_z],
-                                normal: [-1.0, 0.0, 0.0],
-                            },
-                        ]);
-                        indices.extend(&[
-                            vertex_count,
-                            vertex_count + 1,
-                            vertex_count + 2,
-                            vertex_count,
-                            vertex_count + 2,
-                            vertex_count + 3,
-                        ]);
-                        vertex_count += 4;
+                    let mut height = 1;
+                    while v + height < CHUNK_SIZE && mask[u * CHUNK_SIZE + v + height] == Some(cell)
+                    {
+                        height += 1;
                     }
 
-                    if self.voxel_map.get(&[x + 1, y, z]).is_none() {
-                        vertices.extend(&[
-                            Vertex {
-                                position: [pos_x, neg_y, pos_z],
-                                normal: [1.0, 0.0, 0.0],
-                            },
-                            Vertex {
-                                position: [pos_x, neg_y, neg_z],
-                                normal: [1.0, 0.0, 0.0],
-                            },
-                            Vertex {
-                                position: [pos_x, pos_y, neg_z],
-                                normal: [1.0, 0.0, 0.0],
-                            },
-                            Vertex {
-                                position: [pos_x, pos_y, pos_z],
-                                normal: [1.0, 0.0, 0.0],
-                            },
-                        ]);
-                        indices.extend(&[
-                            vertex_count,
-                            vertex_count + 1,
-                            vertex_count + 2,
-                            vertex_count,
-                            vertex_count + 2,
-                            vertex_count + 3,
-                        ]);
-                        vertex_count += 4;
+                    let mut width = 1;
+                    while u + width < CHUNK_SIZE
+                        && (0..height)
+                            .all(|dv| mask[(u + width) * CHUNK_SIZE + v + dv] == Some(cell))
+                    {
+                        width += 1;
                     }
 
-                    if self.voxel_map.get(&[x, y - 1, z]).is_none() {
-                        vertices.extend(&[
-                            Vertex {
-                                position: [neg_x, neg_y, neg_z],
-                                normal: [0.0, -1.0, 0.0],
-                            },
-                            Vertex {
-                                position: [pos_x, neg_y, neg_z],
-                                normal: [0.0, -1.0, 0.0],
-                            },
-                            Vertex {
-                                position: [pos_x, neg_y, pos_z],
-                                normal: [0.0, -1.0, 0.0],
-                            },
-                            Vertex {
-                                position: [neg_x, neg_y, pos_z],
-                                normal: [0.0, -1.0, 0.0],
-                            },
-                        ]);
-                        indices.extend(&[
-                            vertex_count,
-                            vertex_count + 1,
-                            vertex_count + 2,
-                            vertex_count,
-                            vertex_count + 2,
-                            vertex_count + 3,
-                        ]);
-                        vertex_count += 4;
+                    for du in 0..width {
+                        for dv in 0..height {
+                            mask[(u + du) * CHUNK_SIZE + v + dv] = None;
+                        }
                     }
 
-                    if self.voxel_map.get(&[x, y + 1, z]).is_none() {
-                        vertices.extend(&[
-                            Vertex {
-                                position: [neg_x, pos_y, pos_z],
-                                normal: [0.0, 1.0, 0.0],
-                            },
-                            Vertex {
-                                position: [pos_x, pos_y, pos_z],
-                                normal: [0.0, 1.0, 0.0],
-                            },
-                            Vertex {
-                                position: [pos_x, pos_y, neg_z],
-                                normal: [0.0, 1.0, 0.0],
-                            },
-                            Vertex {
-                                position: [neg_x, pos_y, neg_z],
-                                normal: [0.0, 1.0, 0.0],
-                            },
-                        ]);
-                        indices.extend(&[
-                            vertex_count,
-                            vertex_count + 1,
-                            vertex_count + 2,
-                            vertex_count,
-                            vertex_count + 2,
-                            vertex_count + 3,
-                        ]);
-                        vertex_count += 4;
+                    let min = voxel_pos(w, u, v);
+                    let max = voxel_pos(w, u + width - 1, v + height - 1);
+                    let max_positions = face.positions(max);
+                    let mut positions = face.positions(min);
+                    for (corner, max_corner) in positions.iter_mut().zip(max_positions) {
+                        for axis in [u_axis, v_axis] {
+                            if corner[axis] > min[axis] as f32 * VOXEL_SIZE {
+                                corner[axis] = max_corner[axis];
+                            }
+                        }
                     }
+                    quads.push(Quad {
+                        face,
+                        voxel: cell.0,
+                        positions,
+                        ao: cell.1,
+                        light: cell.2,
+                    });
 
-                    if self.voxel_map.get(&[x, y, z - 1]).is_none() {
-                        vertices.extend(&[
-                            Vertex {
-                                position: [pos_x, neg_y, neg_z],
-                                normal: [0.0, 0.0, -1.0],
-                            },
-                            Vertex {
-                                position: [neg_x, neg_y, neg_z],
-                                normal: [0.0, 0.0, -1.0],
-                            },
-                            Vertex {
-                                position: [neg_x, pos_y, neg_z],
-                                normal: [0.0, 0.0, -1.0],
-                            },
-                            Vertex {
-                                position: [pos_x, pos_y, neg_z],
-                                normal: [0.0, 0.0, -1.0],
-                            },
-                        ]);
-                        indices.extend(&[
-                            vertex_count,
-                            vertex_count + 1,
-                            vertex_count + 2,
-                            vertex_count,
-                            vertex_count + 2,
-                            vertex_count + 3,
-                        ]);
-                        vertex_count += 4;
-                    }
+                    v += height;
+                }
+            }
+        }
+    }
+
+    pub fn material() -> StandardMaterial {
+        Color::WHITE.into()
+    }
 
-                    if self.voxel_map.get(&[x, y, z + 1]).is_none() {
-                        vertices.extend(&[
-                            Vertex {
-                                position: [neg_x, neg_y, pos_z],
-                                normal: [0.0, 0.0, 1.0],
-                            },
-                            Vertex {
-                                position: [pos_x, neg_y, pos_z],
-                                normal: [0.0, 0.0, 1.0],
-                            },
-                            Vertex {
-                                position: [pos_x, pos_y, pos_z],
-                                normal: [0.0, 0.0, 1.0],
-                            },
-                            Vertex {
-                                position: [neg_x, pos_y, pos_z],
-                                normal: [0.0, 0.0, 1.0],
-                            },
-                        ]);
-                        indices.extend(&[
-                            vertex_count,
-                            vertex_count + 1,
-                            vertex_count + 2,
-                            vertex_count,
-                            vertex_count + 2,
-                            vertex_count + 3,
-                        ]);
-                        vertex_count += 4;
+    pub fn transparent_material() -> StandardMaterial {
+        StandardMaterial {
+            base_color: Color::rgba(1.0, 1.0, 1.0, 0.5),
+            alpha_mode: AlphaMode::Blend,
+            ..default()
+        }
+    }
+}
+
+impl Default for ChunkStorage {
+    fn default() -> Self {
+        Self::Uniform(None)
+    }
+}
+
+impl ChunkStorage {
+    pub fn get(&self, pos: &[isize; 3]) -> Option<&Voxel> {
+        match self {
+            Self::Uniform(voxel) => voxel.as_ref().filter(|_| Self::in_bounds(pos)),
+            Self::Sparse(voxel_map) => voxel_map.get(pos),
+        }
+    }
+
+    pub fn set(&mut self, pos: [isize; 3], voxel: Option<Voxel>) {
+        if let Self::Uniform(uniform) = self {
+            if *uniform == voxel {
+                return;
+            }
+
+            let mut voxel_map = HashMap::new();
+            if let Some(uniform) = uniform {
+                for x in -(CHUNK_SIZE_PADDED as isize / 2)..CHUNK_SIZE_PADDED as isize / 2 {
+                    for y in -(CHUNK_SIZE_PADDED as isize / 2)..CHUNK_SIZE_PADDED as isize / 2 {
+                        for z in -(CHUNK_SIZE_PADDED as isize / 2)..CHUNK_SIZE_PADDED as isize / 2 {
+                            voxel_map.insert([x, y, z], *uniform);
+                        }
                     }
                 }
             }
+            *self = Self::Sparse(voxel_map);
         }
 
-        let positions = vertices.iter().map(|v| v.position).collect::<Vec<_>>();
-        let normals = vertices.iter().map(|v| v.normal).collect::<Vec<_>>();
-        Mesh::new(PrimitiveTopology::TriangleList)
-            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
-            .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
-            .with_indices(Some(Indices::U32(indices)))
+        if let Self::Sparse(voxel_map) = self {
+            match voxel {
+                Some(voxel) => voxel_map.insert(pos, voxel),
+                None => voxel_map.remove(&pos),
+            };
+        }
+    }
+
+    /// Collapses the storage to `Uniform` if every voxel (padding included) is the same.
+    pub fn compact(&mut self) {
+        if let Self::Sparse(voxel_map) = self {
+            if voxel_map.is_empty() {
+                *self = Self::Uniform(None);
+            } else if voxel_map.len() == CHUNK_SIZE_PADDED.pow(3) {
+                let first = *voxel_map.values().next().unwrap();
+                if voxel_map.values().all(|voxel| *voxel == first) {
+                    *self = Self::Uniform(Some(first));
+                }
+            }
+        }
+
+        if let Self::Sparse(voxel_map) = self {
+            voxel_map.shrink_to_fit();
+        }
+    }
+
+    /// Approximate heap usage, counting one control byte per map slot.
+    pub fn memory_bytes(&self) -> usize {
+        match self {
+            Self::Uniform(_) => 0,
+            Self::Sparse(voxel_map) => {
+                voxel_map.capacity() * (mem::size_of::<([isize; 3], Voxel)>() + 1)
+            }
+        }
+    }
+
+    fn in_bounds(pos: &[isize; 3]) -> bool {
+        pos.iter().all(|&p| {
+            (-(CHUNK_SIZE_PADDED as isize / 2)..CHUNK_SIZE_PADDED as isize / 2).contains(&p)
+        })
+    }
+}
+
+impl Skylight {
+    pub fn get(&self, pos: [isize; 3]) -> u8 {
+        match self {
+            Self::Uniform(level) => *level,
+            Self::Dense(levels) => levels[Chunk::padded_index(pos)],
+        }
+    }
+
+    pub fn set(&mut self, pos: [isize; 3], level: u8) {
+        if let Self::Uniform(uniform) = *self {
+            if uniform == level {
+                return;
+            }
+            *self = Self::Dense(vec![uniform; CHUNK_SIZE_PADDED.pow(3)]);
+        }
+
+        if let Self::Dense(levels) = self {
+            levels[Chunk::padded_index(pos)] = level;
+        }
+    }
+
+    /// Collapses to `Uniform` if every voxel (padding included) has the same level.
+    pub fn compact(&mut self) {
+        if let Self::Dense(levels) = self {
+            if levels.iter().all(|&level| level == levels[0]) {
+                *self = Self::Uniform(levels[0]);
+            }
+        }
+    }
+
+    /// Heap usage, one byte per voxel once dense.
+    pub fn memory_bytes(&self) -> usize {
+        match self {
+            Self::Uniform(_) => 0,
+            Self::Dense(levels) => levels.capacity(),
+        }
     }
 
-    pub fn to_material(&self) -> StandardMaterial {
-        Color::NONE.into()
+    /// Every padded voxel's level, indexed like `Chunk::padded_index`.
+    fn levels(&self) -> Vec<u8> {
+        match self {
+            Self::Uniform(level) => vec![*level; CHUNK_SIZE_PADDED.pow(3)],
+            Self::Dense(levels) => levels.clone(),
+        }
     }
 }
 
@@ -284,3 +626,381 @@ impl PartialEq for Chunk {
             && self.chunk_z == other.chunk_z
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::mesh::VertexAttributeValues;
+
+    use super::*;
+    use crate::{
+        generation::mix_seed,
+        test_utils::{registry, GLASS, STONE},
+    };
+
+    fn mesh_face_count(mesh: &Mesh) -> usize {
+        mesh.indices().map_or(0, |indices| indices.len() / 6)
+    }
+
+    /// Faces the mesher should emit, counted straight from the culling rule: a face shows
+    /// unless another voxel covers it.
+    fn chunk_exposed_faces(chunk: &Chunk) -> usize {
+        let half = CHUNK_SIZE as isize / 2;
+        let mut count = 0;
+        for x in -half..half {
+            for y in -half..half {
+                for z in -half..half {
+                    if chunk.storage.get(&[x, y, z]).is_none() {
+                        continue;
+                    }
+
+                    for [dx, dy, dz] in Face::ALL.map(Face::offset) {
+                        count += chunk.storage.get(&[x + dx, y + dy, z + dz]).is_none() as usize;
+                    }
+                }
+            }
+        }
+        count
+    }
+
+    /// Summed area of the quads in a mesh built by `to_mesh`.
+    fn mesh_area(mesh: &Mesh) -> f32 {
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            panic!("mesh has no positions");
+        };
+        positions
+            .chunks(4)
+            .map(|quad| {
+                let [a, b, _, d] = [0, 1, 2, 3].map(|i| Vec3::from_array(quad[i]));
+                (b - a).cross(d - a).length()
+            })
+            .sum()
+    }
+
+    /// A chunk at `coords` filled with `fill`, with every column open to the sky.
+    fn filled_chunk([chunk_x, chunk_y, chunk_z]: [isize; 3], fill: Option<Voxel>) -> Chunk {
+        Chunk {
+            storage: Arc::new(ChunkStorage::Uniform(fill)),
+            chunk_x,
+            chunk_y,
+            chunk_z,
+            entity: None,
+            column_tops: vec![isize::MIN; CHUNK_SIZE_PADDED.pow(2)],
+            skylight: Skylight::Uniform(MAX_LIGHT),
+            block_light: HashMap::new(),
+            dirty: false,
+        }
+    }
+
+    #[test]
+    fn uniform_storage_expands_on_the_first_different_voxel() {
+        let mut storage = ChunkStorage::Uniform(Some(STONE));
+        storage.set([3, 4, 5], Some(STONE));
+        assert!(matches!(storage, ChunkStorage::Uniform(Some(STONE))));
+
+        storage.set([3, 4, 5], None);
+        let ChunkStorage::Sparse(voxel_map) = &storage else {
+            panic!("storage stayed uniform");
+        };
+        assert_eq!(voxel_map.len(), CHUNK_SIZE_PADDED.pow(3) - 1);
+        assert_eq!(storage.get(&[3, 4, 5]), None);
+        assert_eq!(storage.get(&[3, 4, 6]), Some(&STONE));
+
+        storage.set([3, 4, 5], Some(STONE));
+        storage.compact();
+        assert!(matches!(storage, ChunkStorage::Uniform(Some(STONE))));
+    }
+
+    #[test]
+    fn mesh_faces_match_the_exposed_faces() {
+        let registry = registry();
+        let single = vec![[0, 0, 0]];
+        let cube = (0..8).map(|i| [i & 1, i >> 1 & 1, i >> 2 & 1]).collect();
+        // Straddles the chunk edge, so some voxels sit in the padding.
+        let edge = (14..18).map(|x| [x, 0, 0]).collect();
+        // A scattered pseudo-random half of an 8x8x8 block.
+        let scattered = (0..512)
+            .filter(|&i: &isize| (i as u32).wrapping_mul(2_654_435_761) >> 31 == 1)
+            .map(|i| [i % 8, i / 8 % 8, i / 64])
+            .collect();
+
+        for pattern in [single, cube, edge, scattered] {
+            let mut chunk = filled_chunk([0, 0, 0], None);
+            for pos in pattern {
+                Arc::make_mut(&mut chunk.storage).set(pos, Some(STONE));
+            }
+
+            let settings = MeshingSettings::default();
+            let light = chunk.light(&settings);
+            assert_eq!(
+                mesh_face_count(&chunk.to_mesh(&settings, MeshPass::Opaque, &registry, &light)),
+                chunk_exposed_faces(&chunk)
+            );
+        }
+    }
+
+    #[test]
+    fn material_is_opaque_white() {
+        let material = Chunk::material();
+        assert_eq!(material.base_color, Color::WHITE);
+        assert_eq!(material.alpha_mode, AlphaMode::Opaque);
+    }
+
+    #[test]
+    fn stone_beside_a_corner_darkens_it() {
+        let registry = registry();
+        let mut chunk = filled_chunk([0, 0, 0], None);
+        Arc::make_mut(&mut chunk.storage).set([0, 0, 0], Some(STONE));
+        Arc::make_mut(&mut chunk.storage).set([1, 1, 0], Some(STONE));
+
+        let settings = MeshingSettings::default();
+        let ao = chunk.face_ao([0, 0, 0], Face::Top, &settings, &registry);
+        for (corner, ao) in Face::Top.positions([0, 0, 0]).into_iter().zip(ao) {
+            assert_eq!(ao, if corner[0] > 0.0 { 2 } else { 3 });
+        }
+
+        let light = chunk.light(&settings);
+        let mesh = chunk.to_mesh(&settings, MeshPass::Opaque, &registry, &light);
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            panic!("mesh has no positions");
+        };
+        let Some(VertexAttributeValues::Float32x3(normals)) =
+            mesh.attribute(Mesh::ATTRIBUTE_NORMAL)
+        else {
+            panic!("mesh has no normals");
+        };
+        let Some(VertexAttributeValues::Float32x4(colors)) = mesh.attribute(Mesh::ATTRIBUTE_COLOR)
+        else {
+            panic!("mesh has no colors");
+        };
+        let top = positions
+            .iter()
+            .zip(normals)
+            .zip(colors)
+            .filter(|((position, normal), _)| {
+                **normal == Face::Top.normal()
+                    && position[1] == 0.5
+                    && position[0].abs() == 0.5
+                    && position[2].abs() == 0.5
+            })
+            .map(|((position, _), color)| (position, color))
+            .collect::<Vec<_>>();
+        assert_eq!(top.len(), 4);
+        for (position, color) in top {
+            let expected = if position[0] > 0.0 {
+                AO_BRIGHTNESS[2]
+            } else {
+                1.0
+            };
+            assert_eq!(*color, [expected, expected, expected, 1.0]);
+        }
+    }
+
+    #[test]
+    fn triangles_wind_towards_the_face_normal() {
+        for face in Face::ALL {
+            let corners = face.positions([0, 0, 0]).map(Vec3::from_array);
+            for split in QUAD_TRIANGLES {
+                for triangle in split.chunks(3) {
+                    let [a, b, c] = [0, 1, 2].map(|i| corners[triangle[i] as usize]);
+                    let normal = (b - a).cross(c - a).normalize();
+                    assert_eq!(normal.to_array(), face.normal(), "{face:?} {triangle:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn greedy_meshing_merges_a_slab_into_one_quad_per_face() {
+        let registry = registry();
+        let mut chunk = filled_chunk([0, 0, 0], None);
+        for x in 0..16 {
+            for z in 0..16 {
+                Arc::make_mut(&mut chunk.storage).set([x, 0, z], Some(STONE));
+            }
+        }
+
+        let per_voxel = MeshingSettings::default();
+        let greedy = MeshingSettings {
+            greedy: true,
+            ..per_voxel
+        };
+        let light = chunk.light(&greedy);
+        let greedy_mesh = chunk.to_mesh(&greedy, MeshPass::Opaque, &registry, &light);
+        let per_voxel_mesh = chunk.to_mesh(&per_voxel, MeshPass::Opaque, &registry, &light);
+
+        assert_eq!(mesh_face_count(&greedy_mesh), 6);
+        assert_eq!(mesh_face_count(&per_voxel_mesh), 16 * 16 * 2 + 16 * 4);
+        assert_eq!(mesh_area(&greedy_mesh), mesh_area(&per_voxel_mesh));
+    }
+
+    #[test]
+    fn greedy_meshing_covers_the_same_area() {
+        let registry = registry();
+        let mut chunk = filled_chunk([0, 0, 0], None);
+        // Scattered stone and glass, running over the chunk edge into the padding.
+        for i in 0..1024 {
+            let voxel = [None, Some(STONE), Some(GLASS)][mix_seed(i as u32) as usize % 3];
+            Arc::make_mut(&mut chunk.storage).set([i % 16 + 1, i / 16 % 8, i / 128 - 4], voxel);
+        }
+
+        let per_voxel = MeshingSettings::default();
+        let greedy = MeshingSettings {
+            greedy: true,
+            ..per_voxel
+        };
+        let light = chunk.light(&greedy);
+        for pass in [MeshPass::Opaque, MeshPass::Transparent] {
+            let greedy_mesh = chunk.to_mesh(&greedy, pass, &registry, &light);
+            let per_voxel_mesh = chunk.to_mesh(&per_voxel, pass, &registry, &light);
+            assert!(mesh_face_count(&greedy_mesh) < mesh_face_count(&per_voxel_mesh));
+            assert_eq!(mesh_area(&greedy_mesh), mesh_area(&per_voxel_mesh));
+        }
+    }
+
+    #[test]
+    fn dense_chunks_report_more_memory_than_uniform_ones() {
+        let uniform = filled_chunk([0, 0, 0], Some(STONE));
+        let overhead = mem::size_of::<Chunk>()
+            + mem::size_of::<ChunkStorage>()
+            + CHUNK_SIZE_PADDED.pow(2) * mem::size_of::<isize>();
+        assert_eq!(uniform.memory_bytes(), overhead);
+
+        let mut dense = filled_chunk([0, 0, 0], Some(STONE));
+        Arc::make_mut(&mut dense.storage).set([0, 0, 0], None);
+        let voxels = CHUNK_SIZE_PADDED.pow(3) - 1;
+        let entry = mem::size_of::<([isize; 3], Voxel)>() + 1;
+        assert!(dense.memory_bytes() >= overhead + voxels * entry);
+        assert!(uniform.memory_bytes() * 100 < dense.memory_bytes());
+    }
+
+    #[test]
+    fn histogram_counts_each_voxel_type() {
+        let dirt = Voxel {
+            block: BlockId(1),
+            level: 0,
+        };
+        let mut chunk = filled_chunk([0, 0, 0], None);
+        for x in 0..10 {
+            Arc::make_mut(&mut chunk.storage).set([x, 0, 0], Some(STONE));
+        }
+        for y in 0..5 {
+            Arc::make_mut(&mut chunk.storage).set([0, y, 1], Some(dirt));
+        }
+        // Padding voxels belong to the neighbors and aren't counted.
+        Arc::make_mut(&mut chunk.storage).set([16, 0, 0], Some(STONE));
+        Arc::make_mut(&mut chunk.storage).set([0, -17, 0], Some(dirt));
+
+        let histogram = chunk.histogram();
+        assert_eq!(histogram.len(), 3);
+        assert_eq!(histogram[&Some(STONE)], 10);
+        assert_eq!(histogram[&Some(dirt)], 5);
+        assert_eq!(histogram[&None], CHUNK_SIZE.pow(3) as u32 - 15);
+    }
+
+    #[test]
+    fn adjacent_glass_has_no_face_between() {
+        let registry = registry();
+        let mut chunk = filled_chunk([0, 0, 0], None);
+        Arc::make_mut(&mut chunk.storage).set([0, 0, 0], Some(GLASS));
+        Arc::make_mut(&mut chunk.storage).set([1, 0, 0], Some(GLASS));
+
+        assert_eq!(
+            chunk.visible_voxel([0, 0, 0], Face::Right, MeshPass::Transparent, &registry),
+            None
+        );
+        assert_eq!(
+            chunk.visible_voxel([1, 0, 0], Face::Left, MeshPass::Transparent, &registry),
+            None
+        );
+
+        let settings = MeshingSettings::default();
+        let light = chunk.light(&settings);
+        let transparent = chunk.to_mesh(&settings, MeshPass::Transparent, &registry, &light);
+        let opaque = chunk.to_mesh(&settings, MeshPass::Opaque, &registry, &light);
+        assert_eq!(mesh_face_count(&transparent), 10);
+        assert_eq!(mesh_face_count(&opaque), 0);
+    }
+
+    #[test]
+    fn glass_shows_its_face_against_stone() {
+        let registry = registry();
+        let mut chunk = filled_chunk([0, 0, 0], None);
+        Arc::make_mut(&mut chunk.storage).set([0, 0, 0], Some(GLASS));
+        Arc::make_mut(&mut chunk.storage).set([1, 0, 0], Some(STONE));
+
+        assert_eq!(
+            chunk.visible_voxel([0, 0, 0], Face::Right, MeshPass::Transparent, &registry),
+            Some(GLASS)
+        );
+        assert_eq!(
+            chunk.visible_voxel([1, 0, 0], Face::Left, MeshPass::Opaque, &registry),
+            Some(STONE)
+        );
+
+        let settings = MeshingSettings::default();
+        let light = chunk.light(&settings);
+        let transparent = chunk.to_mesh(&settings, MeshPass::Transparent, &registry, &light);
+        assert_eq!(mesh_face_count(&transparent), 6);
+    }
+
+    #[test]
+    fn water_hides_water_across_the_chunk_edge() {
+        let registry = registry();
+        let water = Voxel {
+            block: BlockId::WATER,
+            level: LIQUID_LEVEL_FULL,
+        };
+        let mut chunk = filled_chunk([0, 0, 0], None);
+        let half = CHUNK_SIZE_PADDED as isize / 2;
+        for x in -half..half {
+            for y in -half..=0 {
+                for z in -half..half {
+                    Arc::make_mut(&mut chunk.storage).set([x, y, z], Some(water));
+                }
+            }
+        }
+
+        let settings = MeshingSettings::default();
+        let light = chunk.light(&settings);
+        let mesh = chunk.to_mesh(&settings, MeshPass::Transparent, &registry, &light);
+        let Some(VertexAttributeValues::Float32x3(normals)) =
+            mesh.attribute(Mesh::ATTRIBUTE_NORMAL)
+        else {
+            panic!("mesh has no normals");
+        };
+        // Only the surface shows; no faces between the edge voxels and the padding beside them.
+        assert_eq!(normals.len(), CHUNK_SIZE.pow(2) * 4);
+        assert!(normals.iter().all(|normal| *normal == Face::Top.normal()));
+    }
+
+    #[test]
+    fn full_water_shows_its_side_above_shallower_water() {
+        let registry = registry();
+        let [full, shallow] = [LIQUID_LEVEL_FULL, 3].map(|level| Voxel {
+            block: BlockId::WATER,
+            level,
+        });
+        let mut chunk = filled_chunk([0, 0, 0], None);
+        Arc::make_mut(&mut chunk.storage).set([0, 0, 0], Some(full));
+        Arc::make_mut(&mut chunk.storage).set([1, 0, 0], Some(shallow));
+
+        let settings = MeshingSettings::default();
+        let light = chunk.light(&settings);
+        let mesh = chunk.to_mesh(&settings, MeshPass::Transparent, &registry, &light);
+        let Some(VertexAttributeValues::Float32x3(normals)) =
+            mesh.attribute(Mesh::ATTRIBUTE_NORMAL)
+        else {
+            panic!("mesh has no normals");
+        };
+        // The full voxel's right side stands above the shallow one, which hides nothing of it;
+        // the shallow voxel's left side is covered by the full one.
+        assert_eq!(normals.len(), 11 * 4);
+        let facing = |face: Face| normals.iter().filter(|n| **n == face.normal()).count() / 4;
+        assert_eq!(facing(Face::Right), 2);
+        assert_eq!(facing(Face::Left), 1);
+    }
+}
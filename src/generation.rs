@@ -0,0 +1,251 @@
+use bevy::prelude::*;
+use noise::{NoiseFn, OpenSimplex, Perlin, Simplex};
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::{SEA_LEVEL, SURFACE_AMPLITUDE};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoiseKind {
+    Perlin,
+    Simplex,
+    OpenSimplex,
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct TerrainParams {
+    pub noises: Vec<(NoiseKind, f64)>,
+    pub erosion: Option<ErosionParams>,
+    /// Air at or below this height is filled with water.
+    pub water_level: Option<isize>,
+}
+
+impl Default for TerrainParams {
+    fn default() -> Self {
+        Self {
+            noises: vec![(NoiseKind::Perlin, 1.0)],
+            erosion: None,
+            water_level: Some(SEA_LEVEL),
+        }
+    }
+}
+
+/// Thermal erosion: material slides off any column more than `talus` voxels above a neighbor, and
+/// keeps sliding until the slope settles at half the talus, as loose rock does.
+#[derive(Clone, Copy, Debug)]
+pub struct ErosionParams {
+    pub iterations: usize,
+    pub talus: f64,
+    pub rate: f64,
+}
+
+impl Default for ErosionParams {
+    fn default() -> Self {
+        Self {
+            iterations: 8,
+            talus: 1.0,
+            rate: 0.2,
+        }
+    }
+}
+
+impl ErosionParams {
+    fn step(&self, heights: &[f64], width: usize, depth: usize) -> Vec<f64> {
+        let settle = self.talus / 2.0;
+        let mut next = heights.to_vec();
+        for z in 0..depth {
+            for x in 0..width {
+                let i = z * width + x;
+                for (nx, nz) in [
+                    (x.wrapping_sub(1), z),
+                    (x + 1, z),
+                    (x, z.wrapping_sub(1)),
+                    (x, z + 1),
+                ] {
+                    if nx >= width || nz >= depth {
+                        continue;
+                    }
+
+                    let diff = heights[i] - heights[nz * width + nx];
+                    if diff > self.talus {
+                        next[i] -= (diff - settle) * self.rate;
+                    } else if -diff > self.talus {
+                        next[i] += (-diff - settle) * self.rate;
+                    }
+                }
+            }
+        }
+        next
+    }
+}
+
+pub struct TerrainNoise {
+    sources: Vec<(Box<dyn NoiseFn<f64, 2> + Send + Sync>, f64)>,
+    erosion: Option<ErosionParams>,
+    pub water_level: Option<isize>,
+}
+
+impl TerrainNoise {
+    pub fn new(seed: u32, params: &TerrainParams) -> Self {
+        let seed = mix_seed(seed);
+        let sources = params
+            .noises
+            .iter()
+            .map(|&(kind, weight)| {
+                let noise: Box<dyn NoiseFn<f64, 2> + Send + Sync> = match kind {
+                    NoiseKind::Perlin => Box::new(Perlin::new(seed)),
+                    NoiseKind::Simplex => Box::new(Simplex::new(seed)),
+                    NoiseKind::OpenSimplex => Box::new(OpenSimplex::new(seed)),
+                };
+                (noise, weight)
+            })
+            .collect();
+
+        Self {
+            sources,
+            erosion: params.erosion,
+            water_level: params.water_level,
+        }
+    }
+
+    pub fn get(&self, point: [f64; 2]) -> f64 {
+        let total_weight: f64 = self.sources.iter().map(|(_, weight)| weight).sum();
+        if total_weight == 0.0 {
+            return 0.0;
+        }
+
+        self.sources
+            .iter()
+            .map(|(noise, weight)| noise.get(point) * weight)
+            .sum::<f64>()
+            / total_weight
+    }
+}
+
+/// MurmurHash3's 32-bit finalizer, so neighboring seeds don't share near-identical permutation
+/// tables.
+pub fn mix_seed(seed: u32) -> u32 {
+    let mut h = seed;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^= h >> 16;
+    h
+}
+
+pub fn surface_height(noise: &TerrainNoise, world_x: f64, world_z: f64) -> f64 {
+    SEA_LEVEL as f64 + noise.get([world_x * 0.01, world_z * 0.01]) * SURFACE_AMPLITUDE
+}
+
+/// Surface heights for a `size_x` by `size_z` block of columns, indexed `z * size_x + x`.
+///
+/// With erosion enabled the block is sampled with a margin of one column per iteration, which is
+/// as far as any column's result can reach, so adjacent chunks agree on their shared columns.
+pub fn surface_heights(
+    noise: &TerrainNoise,
+    min_x: isize,
+    min_z: isize,
+    size_x: usize,
+    size_z: usize,
+) -> Vec<isize> {
+    let pad = noise.erosion.map_or(0, |erosion| erosion.iterations);
+    let width = size_x + pad * 2;
+    let depth = size_z + pad * 2;
+
+    let mut heights = (0..width * depth)
+        .map(|i| {
+            let world_x = min_x - pad as isize + (i % width) as isize;
+            let world_z = min_z - pad as isize + (i / width) as isize;
+            surface_height(noise, world_x as f64, world_z as f64)
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(erosion) = noise.erosion {
+        for _ in 0..erosion.iterations {
+            heights = erosion.step(&heights, width, depth);
+        }
+    }
+
+    (0..size_z)
+        .flat_map(|z| (0..size_x).map(move |x| (z, x)))
+        .map(|(z, x)| heights[(z + pad) * width + x + pad].round() as isize)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heightmap(seed: u32, params: &TerrainParams) -> Vec<isize> {
+        surface_heights(&TerrainNoise::new(seed, params), 0, 0, 32, 32)
+    }
+
+    #[test]
+    fn neighboring_seeds_give_different_terrain() {
+        let params = TerrainParams::default();
+        let a = heightmap(1000, &params);
+        let b = heightmap(1001, &params);
+        assert_eq!(a, heightmap(1000, &params));
+        let differing = a.iter().zip(&b).filter(|(a, b)| a != b).count();
+        assert!(differing > a.len() * 9 / 10, "{differing} columns differ");
+        let mean_gap =
+            a.iter().zip(&b).map(|(a, b)| (a - b).abs()).sum::<isize>() / a.len() as isize;
+        assert!(mean_gap > 5, "mean gap {mean_gap}");
+    }
+
+    #[test]
+    fn noise_kinds_give_different_terrain() {
+        let only = |kind| TerrainParams {
+            noises: vec![(kind, 1.0)],
+            ..default()
+        };
+        let perlin = heightmap(0, &only(NoiseKind::Perlin));
+        let simplex = heightmap(0, &only(NoiseKind::Simplex));
+        let differing = perlin.iter().zip(&simplex).filter(|(p, s)| p != s).count();
+        assert!(differing > perlin.len() / 2, "{differing} columns differ");
+    }
+
+    #[test]
+    fn erosion_flattens_spikes_to_the_talus() {
+        let erosion = ErosionParams::default();
+        let size = 32;
+        // Harsh spikes: every column a pseudo-random 0 to 5 voxels high.
+        let mut heights = (0..size * size)
+            .map(|i| (mix_seed(i as u32) % 6) as f64)
+            .collect::<Vec<_>>();
+        let max_slope = |heights: &[f64]| {
+            let mut max = 0.0f64;
+            for z in 0..size - 1 {
+                for x in 0..size - 1 {
+                    let h = heights[z * size + x];
+                    max = max
+                        .max((h - heights[z * size + x + 1]).abs())
+                        .max((h - heights[(z + 1) * size + x]).abs());
+                }
+            }
+            max
+        };
+        assert!(max_slope(&heights) > erosion.talus);
+        for _ in 0..erosion.iterations {
+            heights = erosion.step(&heights, size, size);
+        }
+        assert!(max_slope(&heights) <= erosion.talus);
+    }
+
+    #[test]
+    fn eroded_blocks_agree_on_shared_columns() {
+        let params = TerrainParams {
+            erosion: Some(ErosionParams::default()),
+            ..default()
+        };
+        let noise = TerrainNoise::new(0, &params);
+        let size = 24;
+        let left = surface_heights(&noise, 0, 0, size, size);
+        let right = surface_heights(&noise, 16, 8, size, size);
+        for z in 8..size {
+            for x in 16..size {
+                assert_eq!(left[z * size + x], right[(z - 8) * size + x - 16]);
+            }
+        }
+    }
+}
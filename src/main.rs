@@ -1,8 +1,15 @@
-use bevy::{pbr::wireframe::WireframePlugin, prelude::*};
+use bevy::{diagnostic::LogDiagnosticsPlugin, pbr::wireframe::WireframePlugin, prelude::*};
 
+mod block;
 mod chunk;
+mod generation;
 mod player;
+mod render;
+mod settings;
+mod sky;
 mod terrain;
+#[cfg(test)]
+mod test_utils;
 mod voxel;
 
 fn main() {
@@ -10,7 +17,12 @@ fn main() {
         .add_plugins((
             DefaultPlugins,
             WireframePlugin,
+            block::BlockPlugin,
+            LogDiagnosticsPlugin::filtered(vec![terrain::CHUNK_MEMORY]),
             player::PlayerPlugin,
+            render::RenderSettingsPlugin,
+            settings::SettingsPlugin,
+            sky::SkyPlugin,
             terrain::TerrainPlugin,
         ))
         .add_systems(Startup, setup)
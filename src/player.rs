@@ -1,13 +1,49 @@
 use bevy::{input::mouse::MouseMotion, prelude::*};
 
+use crate::{
+    block::{BlockId, BlockRegistry},
+    chunk::Chunk,
+    terrain::Terrain,
+    voxel::{Face, Voxel, LIQUID_LEVEL_FULL, VOXEL_SIZE},
+};
+
 pub const PLAYER_SPEED: f32 = 20.0;
+pub const PLAYER_SENSITIVITY: f32 = 0.005;
+pub const PLAYER_FOV_DEGREES: f32 = 45.0;
+pub const PLAYER_SPAWN: Vec3 = Vec3::new(-64.0, 64.0, -64.0);
+pub const PLAYER_SPAWN_YAW_DEGREES: f32 = -135.0;
+pub const PLAYER_SPAWN_PITCH_DEGREES: f32 = -35.26;
+pub const VOID_MIN_Y: f32 = -256.0;
+pub const SURFACE_CLEARANCE: f32 = 2.0;
+pub const PLAYER_REACH: f32 = 5.0;
+pub const PLAYER_WIDTH: f32 = 0.6;
+pub const PLAYER_HEIGHT: f32 = 1.8;
+pub const PLAYER_EYE_HEIGHT: f32 = 1.6;
+pub const EXPLOSION_RADIUS: f32 = 4.0;
 
 pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup)
-            .add_systems(Update, move_player);
+        app.init_resource::<PlayerSpawn>()
+            .init_resource::<PlayerSettings>()
+            .init_resource::<VoidSettings>()
+            .init_resource::<SelectedBlock>()
+            .add_systems(Startup, setup)
+            .add_systems(
+                Update,
+                (
+                    apply_player_settings,
+                    move_player,
+                    teleport_to_surface,
+                    check_void,
+                    select_block,
+                    break_block,
+                    explode_block,
+                    place_block,
+                )
+                    .chain(),
+            );
     }
 }
 
@@ -20,11 +56,71 @@ pub struct PlayerBundle {
 #[derive(Component, Clone, Copy, Debug)]
 pub struct Player;
 
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct PlayerSettings {
+    pub sensitivity: f32,
+    pub invert_y: bool,
+    pub fov_degrees: f32,
+}
+
+impl Default for PlayerSettings {
+    fn default() -> Self {
+        Self {
+            sensitivity: PLAYER_SENSITIVITY,
+            invert_y: false,
+            fov_degrees: PLAYER_FOV_DEGREES,
+        }
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct PlayerSpawn {
+    pub position: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl Default for PlayerSpawn {
+    fn default() -> Self {
+        Self {
+            position: PLAYER_SPAWN,
+            yaw: PLAYER_SPAWN_YAW_DEGREES.to_radians(),
+            pitch: PLAYER_SPAWN_PITCH_DEGREES.to_radians(),
+        }
+    }
+}
+
+impl PlayerSpawn {
+    pub fn transform(&self) -> Transform {
+        Transform::from_translation(self.position).with_rotation(Quat::from_euler(
+            EulerRot::YXZ,
+            self.yaw,
+            self.pitch,
+            0.0,
+        ))
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct VoidSettings {
+    pub min_y: f32,
+}
+
+impl Default for VoidSettings {
+    fn default() -> Self {
+        Self { min_y: VOID_MIN_Y }
+    }
+}
+
+/// The block placed on right click.
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct SelectedBlock(pub Voxel);
+
 impl PlayerBundle {
-    pub fn new() -> Self {
+    pub fn new(spawn: &PlayerSpawn) -> Self {
         Self {
             camera_bundle: Camera3dBundle {
-                transform: Transform::from_xyz(-64.0, 64.0, -64.0).looking_at(Vec3::ZERO, Vec3::Y),
+                transform: spawn.transform(),
                 ..default()
             },
             player: Player,
@@ -32,13 +128,27 @@ impl PlayerBundle {
     }
 }
 
-fn setup(mut commands: Commands) {
-    commands.spawn(PlayerBundle::new());
+fn setup(mut commands: Commands, spawn: Res<PlayerSpawn>) {
+    commands.spawn(PlayerBundle::new(&spawn));
+}
+
+fn apply_player_settings(
+    settings: Res<PlayerSettings>,
+    mut query: Query<&mut Projection, With<Player>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    if let Projection::Perspective(perspective) = query.single_mut().into_inner() {
+        perspective.fov = settings.fov_degrees.to_radians();
+    }
 }
 
-fn move_player(
+pub fn move_player(
     time: Res<Time>,
     keys: Res<Input<KeyCode>>,
+    settings: Res<PlayerSettings>,
     mut e_motion: EventReader<MouseMotion>,
     mut query: Query<&mut Transform, With<Player>>,
 ) {
@@ -47,20 +157,405 @@ fn move_player(
     let local_x = transform.local_x() * (Vec3::X + Vec3::Z);
     let local_z = transform.local_z() * (Vec3::X + Vec3::Z);
 
+    let mut direction = Vec3::ZERO;
     for key in keys.get_pressed() {
         match key {
-            KeyCode::W => transform.translation -= local_z * PLAYER_SPEED * time.delta_seconds(),
-            KeyCode::A => transform.translation -= local_x * PLAYER_SPEED * time.delta_seconds(),
-            KeyCode::S => transform.translation += local_z * PLAYER_SPEED * time.delta_seconds(),
-            KeyCode::D => transform.translation += local_x * PLAYER_SPEED * time.delta_seconds(),
+            KeyCode::W => direction -= local_z,
+            KeyCode::A => direction -= local_x,
+            KeyCode::S => direction += local_z,
+            KeyCode::D => direction += local_x,
             KeyCode::Space => transform.translation.y += PLAYER_SPEED * time.delta_seconds(),
             KeyCode::ShiftLeft => transform.translation.y -= PLAYER_SPEED * time.delta_seconds(),
             _ => {}
         }
     }
+    transform.translation += direction.normalize_or_zero() * PLAYER_SPEED * time.delta_seconds();
 
+    let invert_y = if settings.invert_y { -1.0 } else { 1.0 };
     for ev in e_motion.read() {
-        transform.rotate_y(-ev.delta.x * 0.005);
-        transform.rotate_local_x(-ev.delta.y * 0.005);
+        transform.rotate_y(-ev.delta.x * settings.sensitivity);
+        transform.rotate_local_x(-ev.delta.y * settings.sensitivity * invert_y);
+    }
+}
+
+fn check_void(
+    spawn: Res<PlayerSpawn>,
+    void: Res<VoidSettings>,
+    mut query: Query<&mut Transform, With<Player>>,
+) {
+    let mut transform = query.single_mut();
+
+    if transform.translation.y < void.min_y {
+        transform.translation = spawn.position;
+    }
+}
+
+fn teleport_to_surface(
+    keys: Res<Input<KeyCode>>,
+    registry: Res<BlockRegistry>,
+    terrain: Res<Terrain>,
+    mut query: Query<&mut Transform, With<Player>>,
+) {
+    if !keys.just_pressed(KeyCode::T) {
+        return;
+    }
+
+    let mut transform = query.single_mut();
+    let x = (transform.translation.x / VOXEL_SIZE).round() as isize;
+    let z = (transform.translation.z / VOXEL_SIZE).round() as isize;
+
+    let Some(top) = terrain.highest_voxel(x, z) else {
+        info!("No surface found below ({x}, {z})");
+        return;
+    };
+
+    transform.translation.y = (top as f32 + 0.5) * VOXEL_SIZE + SURFACE_CLEARANCE;
+    if let Some(bed) = terrain
+        .highest_solid(x, z, &registry)
+        .filter(|&bed| bed < top)
+    {
+        info!("Teleported above liquid {} voxels deep", top - bed);
+    }
+}
+
+fn break_block(
+    buttons: Res<Input<MouseButton>>,
+    registry: Res<BlockRegistry>,
+    mut terrain: ResMut<Terrain>,
+    query: Query<&Transform, With<Player>>,
+) {
+    if !buttons.just_pressed(MouseButton::Left) {
+        return;
+    }
+
+    let transform = query.single();
+    if let Some((voxel, _)) = terrain.raycast(
+        transform.translation,
+        transform.forward(),
+        PLAYER_REACH,
+        &registry,
+    ) {
+        terrain.set_voxel(voxel, None, &registry);
+    }
+}
+
+/// Middle-clicking blows a hole around the targeted block.
+fn explode_block(
+    buttons: Res<Input<MouseButton>>,
+    registry: Res<BlockRegistry>,
+    mut terrain: ResMut<Terrain>,
+    query: Query<&Transform, With<Player>>,
+) {
+    if !buttons.just_pressed(MouseButton::Middle) {
+        return;
+    }
+
+    let transform = query.single();
+    if let Some((voxel, _)) = terrain.raycast(
+        transform.translation,
+        transform.forward(),
+        PLAYER_REACH,
+        &registry,
+    ) {
+        let center = Vec3::from_array(voxel.map(|v| v as f32)) * VOXEL_SIZE;
+        let removed = terrain.explode(center, EXPLOSION_RADIUS, &registry);
+        info!("Explosion removed {removed} voxels");
+    }
+}
+
+/// Number keys pick the block with id one less than the key, if it's registered.
+fn select_block(
+    keys: Res<Input<KeyCode>>,
+    registry: Res<BlockRegistry>,
+    mut selected: ResMut<SelectedBlock>,
+) {
+    let digits = [
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+        KeyCode::Key8,
+        KeyCode::Key9,
+    ];
+    let Some(id) = digits.iter().position(|key| keys.just_pressed(*key)) else {
+        return;
+    };
+
+    let block = BlockId(id as u16);
+    match registry.get(block) {
+        Some(def) => {
+            info!("Selected {}", def.name);
+            let mut voxel = Voxel { block, level: 0 };
+            if registry.is_liquid(&voxel) {
+                voxel.level = LIQUID_LEVEL_FULL;
+            }
+            selected.0 = voxel;
+        }
+        None => info!("No block registered with id {}", block.0),
+    }
+}
+
+fn place_block(
+    buttons: Res<Input<MouseButton>>,
+    selected: Res<SelectedBlock>,
+    registry: Res<BlockRegistry>,
+    mut terrain: ResMut<Terrain>,
+    query: Query<&Transform, With<Player>>,
+) {
+    if !buttons.just_pressed(MouseButton::Right) {
+        return;
+    }
+
+    let transform = query.single();
+    let Some((hit, face)) = terrain.raycast(
+        transform.translation,
+        transform.forward(),
+        PLAYER_REACH,
+        &registry,
+    ) else {
+        return;
+    };
+
+    if let Some(target) = placement_target(&terrain, &registry, hit, face, transform.translation) {
+        terrain.set_voxel(target, Some(selected.0), &registry);
+    }
+}
+
+/// The voxel a block placed against `face` of `hit` goes in, unless a solid block already fills
+/// it, its chunk isn't loaded, or it overlaps the player whose eye is at `eye`.
+fn placement_target(
+    terrain: &Terrain,
+    registry: &BlockRegistry,
+    hit: [isize; 3],
+    face: Face,
+    eye: Vec3,
+) -> Option<[isize; 3]> {
+    let offset = face.offset();
+    let target = [0, 1, 2].map(|axis| hit[axis] + offset[axis]);
+    terrain.chunk(Chunk::voxel_coords(target).0)?;
+    if terrain
+        .voxel(target)
+        .is_some_and(|voxel| !registry.is_liquid(&voxel))
+    {
+        return None;
+    }
+
+    // The camera sits at eye height, so the body extends mostly below it.
+    let player_min = eye - Vec3::new(PLAYER_WIDTH / 2.0, PLAYER_EYE_HEIGHT, PLAYER_WIDTH / 2.0);
+    let player_max = eye
+        + Vec3::new(
+            PLAYER_WIDTH / 2.0,
+            PLAYER_HEIGHT - PLAYER_EYE_HEIGHT,
+            PLAYER_WIDTH / 2.0,
+        );
+    let center = Vec3::from_array(target.map(|t| t as f32)) * VOXEL_SIZE;
+    let voxel_min = center - VOXEL_SIZE / 2.0;
+    let voxel_max = center + VOXEL_SIZE / 2.0;
+    if player_min.cmplt(voxel_max).all() && voxel_min.cmplt(player_max).all() {
+        return None;
+    }
+
+    Some(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::{
+        chunk::CHUNK_SIZE,
+        test_utils::{registry, sky_terrain, SKY, SKY_CHUNK, STONE},
+    };
+
+    #[test]
+    fn falling_into_the_void_returns_to_spawn() {
+        let mut world = World::new();
+        world.init_resource::<PlayerSpawn>();
+        world.init_resource::<VoidSettings>();
+        let player = world
+            .spawn((Player, Transform::from_xyz(3.0, VOID_MIN_Y - 1.0, 7.0)))
+            .id();
+
+        world.run_system_once(check_void);
+        assert_eq!(
+            world.get::<Transform>(player).unwrap().translation,
+            PLAYER_SPAWN
+        );
+    }
+
+    fn displacement_with(keys: &[KeyCode]) -> Vec3 {
+        let mut world = World::new();
+        let mut time = Time::<()>::default();
+        time.advance_by(Duration::from_millis(100));
+        world.insert_resource(time);
+        let mut input = Input::<KeyCode>::default();
+        for &key in keys {
+            input.press(key);
+        }
+        world.insert_resource(input);
+        world.init_resource::<PlayerSettings>();
+        world.init_resource::<Events<MouseMotion>>();
+        let player = world
+            .spawn((Player, PlayerSpawn::default().transform()))
+            .id();
+
+        world.run_system_once(move_player);
+        world.get::<Transform>(player).unwrap().translation - PLAYER_SPAWN
+    }
+
+    #[test]
+    fn diagonal_movement_is_not_faster() {
+        let forward = displacement_with(&[KeyCode::W]).length();
+        let diagonal = displacement_with(&[KeyCode::W, KeyCode::D]).length();
+        assert!((forward - PLAYER_SPEED * 0.1).abs() < 1e-4);
+        assert!((forward - diagonal).abs() < 1e-4);
+    }
+
+    #[test]
+    fn spawn_yaw_turns_the_player() {
+        let spawn = PlayerSpawn {
+            position: Vec3::ZERO,
+            yaw: 90f32.to_radians(),
+            pitch: 0.0,
+        };
+        let forward = spawn.transform().forward();
+        assert!(forward.abs_diff_eq(Vec3::NEG_X, 1e-5), "{forward}");
+    }
+
+    fn teleported(terrain: Terrain, start: Vec3) -> Vec3 {
+        let mut world = World::new();
+        let mut keys = Input::<KeyCode>::default();
+        keys.press(KeyCode::T);
+        world.insert_resource(keys);
+        world.insert_resource(registry());
+        world.insert_resource(terrain);
+        let player = world
+            .spawn((Player, Transform::from_translation(start)))
+            .id();
+
+        world.run_system_once(teleport_to_surface);
+        world.get::<Transform>(player).unwrap().translation
+    }
+
+    #[test]
+    fn teleport_lifts_the_player_out_of_the_ground() {
+        let registry = registry();
+        let mut terrain = sky_terrain(&[[0, 0, 0], [0, 1, 0]]);
+        let surface = SKY + 20;
+        for y in SKY - 10..=surface {
+            terrain.set_voxel([2, y, -3], Some(STONE), &registry);
+        }
+
+        let underground = Vec3::new(2.2, SKY as f32, -2.9) * VOXEL_SIZE;
+        let translation = teleported(terrain, underground);
+        assert_eq!(
+            translation.y,
+            (surface as f32 + 0.5) * VOXEL_SIZE + SURFACE_CLEARANCE
+        );
+        assert_eq!(translation.xz(), underground.xz());
+    }
+
+    #[test]
+    fn teleport_lands_on_top_of_water() {
+        let registry = registry();
+        let mut terrain = sky_terrain(&[[0, 0, 0]]);
+        let water = Voxel {
+            block: BlockId::WATER,
+            level: LIQUID_LEVEL_FULL,
+        };
+        terrain.set_voxel([0, SKY - 5, 0], Some(STONE), &registry);
+        for y in SKY - 4..=SKY {
+            terrain.set_voxel([0, y, 0], Some(water), &registry);
+        }
+
+        let translation = teleported(terrain, Vec3::new(0.0, SKY as f32 - 5.0, 0.0));
+        assert_eq!(
+            translation.y,
+            (SKY as f32 + 0.5) * VOXEL_SIZE + SURFACE_CLEARANCE
+        );
+    }
+
+    #[test]
+    fn placing_on_top_of_a_chunk_wraps_into_the_one_above() {
+        let registry = registry();
+        let mut terrain = sky_terrain(&[[0, 0, 0], [0, 1, 0]]);
+        let hit = [0, SKY + CHUNK_SIZE as isize / 2 - 1, 0];
+        let eye = Vec3::splat(100.0);
+
+        let target = placement_target(&terrain, &registry, hit, Face::Top, eye);
+        assert_eq!(target, Some([0, SKY + CHUNK_SIZE as isize / 2, 0]));
+
+        terrain.set_voxel(target.unwrap(), Some(STONE), &registry);
+        let above = terrain.chunk([0, SKY_CHUNK + 1, 0]).unwrap();
+        let half = CHUNK_SIZE as isize / 2;
+        assert_eq!(above.storage.get(&[0, -half, 0]), Some(&STONE));
+    }
+
+    #[test]
+    fn placing_into_an_unloaded_chunk_is_refused() {
+        let registry = registry();
+        let terrain = sky_terrain(&[[0, 0, 0]]);
+        let hit = [0, SKY + CHUNK_SIZE as isize / 2 - 1, 0];
+        let eye = Vec3::splat(100.0);
+
+        assert_eq!(
+            placement_target(&terrain, &registry, hit, Face::Top, eye),
+            None
+        );
+    }
+
+    #[test]
+    fn placing_inside_the_player_is_refused() {
+        let registry = registry();
+        let mut terrain = sky_terrain(&[[0, 0, 0]]);
+        terrain.set_voxel([0, SKY, 0], Some(STONE), &registry);
+        let eye = Vec3::new(0.0, SKY as f32 + 2.0, 0.0) * VOXEL_SIZE;
+
+        assert_eq!(
+            placement_target(&terrain, &registry, [0, SKY, 0], Face::Top, eye),
+            None
+        );
+        assert_eq!(
+            placement_target(&terrain, &registry, [0, SKY, 0], Face::Right, eye),
+            Some([1, SKY, 0])
+        );
+    }
+
+    #[test]
+    fn only_liquids_are_selected_with_a_level() {
+        let mut world = World::new();
+        world.insert_resource(registry());
+        world.init_resource::<SelectedBlock>();
+        let select = |world: &mut World, key| {
+            let mut input = Input::<KeyCode>::default();
+            input.press(key);
+            world.insert_resource(input);
+            world.run_system_once(select_block);
+            world.resource::<SelectedBlock>().0
+        };
+
+        let stone = select(&mut world, KeyCode::Key1);
+        assert_eq!(
+            stone,
+            Voxel {
+                block: BlockId::STONE,
+                level: 0
+            }
+        );
+        let water = select(&mut world, KeyCode::Key5);
+        assert_eq!(
+            water,
+            Voxel {
+                block: BlockId::WATER,
+                level: LIQUID_LEVEL_FULL
+            }
+        );
+        assert_eq!(select(&mut world, KeyCode::Key9), water);
     }
 }
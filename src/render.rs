@@ -0,0 +1,215 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::{prelude::*, render::view::screenshot::ScreenshotManager, window::PrimaryWindow};
+
+use crate::{chunk::Chunk, player::Player, terrain::WORLD_SEED};
+
+pub const RENDER_DISTANCE_CHUNKS: usize = 8;
+pub const SCREENSHOT_KEY: KeyCode = KeyCode::F2;
+pub const SCREENSHOT_CAPTION_FONT_SIZE: f32 = 20.0;
+
+pub struct RenderSettingsPlugin;
+
+impl Plugin for RenderSettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RenderSettings>()
+            .add_systems(Update, (cycle_msaa, apply_render_settings).chain())
+            .add_systems(Update, (remove_screenshot_caption, take_screenshot).chain());
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct RenderSettings {
+    pub msaa: Msaa,
+    pub render_distance_chunks: usize,
+    /// Whether directional lights cast shadows.
+    pub shadows_enabled: bool,
+    pub screenshot_key: KeyCode,
+    /// Burns the seed and player position into screenshots.
+    pub screenshot_caption: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            msaa: Msaa::default(),
+            render_distance_chunks: RENDER_DISTANCE_CHUNKS,
+            shadows_enabled: true,
+            screenshot_key: SCREENSHOT_KEY,
+            screenshot_caption: true,
+        }
+    }
+}
+
+fn cycle_msaa(keys: Res<Input<KeyCode>>, mut settings: ResMut<RenderSettings>) {
+    if !keys.just_pressed(KeyCode::F3) {
+        return;
+    }
+
+    settings.msaa = match settings.msaa {
+        Msaa::Off => Msaa::Sample2,
+        Msaa::Sample2 => Msaa::Sample4,
+        Msaa::Sample4 => Msaa::Sample8,
+        Msaa::Sample8 => Msaa::Off,
+    };
+    info!("MSAA: {}x", settings.msaa.samples());
+}
+
+/// Text shown for the one frame a screenshot captures.
+#[derive(Component, Clone, Copy, Debug)]
+struct ScreenshotCaption;
+
+fn apply_render_settings(settings: Res<RenderSettings>, mut msaa: ResMut<Msaa>) {
+    if settings.is_changed() {
+        *msaa = settings.msaa;
+    }
+}
+
+fn screenshot_metadata(seed: u32, translation: Vec3) -> String {
+    format!(
+        "seed {seed} | pos {:.1} {:.1} {:.1} | chunk {:?}",
+        translation.x,
+        translation.y,
+        translation.z,
+        Chunk::coords_at(translation),
+    )
+}
+
+/// File name for a screenshot taken `timestamp` milliseconds after the Unix epoch.
+fn screenshot_path(seed: u32, timestamp: u128) -> String {
+    format!("screenshot-seed{seed}-{timestamp}.png")
+}
+
+fn take_screenshot(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    settings: Res<RenderSettings>,
+    mut screenshot_manager: ResMut<ScreenshotManager>,
+    q_window: Query<Entity, With<PrimaryWindow>>,
+    q_player: Query<&Transform, With<Player>>,
+) {
+    if !keys.just_pressed(settings.screenshot_key) {
+        return;
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    let path = screenshot_path(WORLD_SEED, timestamp);
+    let metadata = screenshot_metadata(WORLD_SEED, q_player.single().translation);
+
+    // The image arrives once the frame has rendered, so only then do we know if saving worked.
+    let caption = metadata.clone();
+    let result = screenshot_manager.take_screenshot(q_window.single(), move |image| {
+        let saved = image
+            .try_into_dynamic()
+            .map_err(|err| err.to_string())
+            .and_then(|image| image.to_rgb8().save(&path).map_err(|err| err.to_string()));
+        match saved {
+            Ok(()) => info!("Saved {path} ({metadata})"),
+            Err(err) => error!("Failed to save {path}: {err}"),
+        }
+    });
+    if let Err(err) = result {
+        error!("Failed to take screenshot: {err}");
+        return;
+    }
+
+    if settings.screenshot_caption {
+        commands.spawn((
+            TextBundle::from_section(
+                caption,
+                TextStyle {
+                    font_size: SCREENSHOT_CAPTION_FONT_SIZE,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                left: Val::Px(8.0),
+                bottom: Val::Px(8.0),
+                ..default()
+            }),
+            ScreenshotCaption,
+        ));
+    }
+}
+
+fn remove_screenshot_caption(
+    mut commands: Commands,
+    q_caption: Query<Entity, With<ScreenshotCaption>>,
+) {
+    for entity in &q_caption {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    #[test]
+    fn cycling_msaa_updates_the_resource() {
+        let mut world = World::new();
+        let mut keys = Input::<KeyCode>::default();
+        keys.press(KeyCode::F3);
+        world.insert_resource(keys);
+        world.insert_resource(RenderSettings {
+            msaa: Msaa::Sample4,
+            ..default()
+        });
+        world.insert_resource(Msaa::Sample4);
+
+        world.run_system_once(cycle_msaa);
+        world.run_system_once(apply_render_settings);
+        assert_eq!(*world.resource::<Msaa>(), Msaa::Sample8);
+    }
+
+    #[test]
+    fn screenshot_names_carry_the_seed() {
+        assert_eq!(
+            screenshot_path(1234, 1_700_000_000_123),
+            "screenshot-seed1234-1700000000123.png"
+        );
+        assert_ne!(
+            screenshot_path(1234, 1_700_000_000_123),
+            screenshot_path(1234, 1_700_000_000_124)
+        );
+        assert_eq!(
+            screenshot_metadata(1234, Vec3::new(40.0, -3.3, 0.0)),
+            "seed 1234 | pos 40.0 -3.3 0.0 | chunk [1, 0, 0]"
+        );
+    }
+
+    #[test]
+    fn screenshots_are_captioned_for_one_frame() {
+        let mut world = World::new();
+        let mut keys = Input::<KeyCode>::default();
+        keys.press(KeyCode::F12);
+        world.insert_resource(keys);
+        world.insert_resource(RenderSettings {
+            screenshot_key: KeyCode::F12,
+            ..default()
+        });
+        world.init_resource::<ScreenshotManager>();
+        world.spawn(PrimaryWindow);
+        world.spawn((Player, Transform::from_xyz(40.0, -3.3, 0.0)));
+
+        world.run_system_once(take_screenshot);
+        let mut q_caption = world.query_filtered::<&Text, With<ScreenshotCaption>>();
+        let captions = q_caption
+            .iter(&world)
+            .map(|text| text.sections[0].value.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            captions,
+            vec![screenshot_metadata(WORLD_SEED, Vec3::new(40.0, -3.3, 0.0))]
+        );
+
+        world.run_system_once(remove_screenshot_caption);
+        assert_eq!(q_caption.iter(&world).count(), 0);
+    }
+}
@@ -0,0 +1,189 @@
+use std::fs;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    generation::{NoiseKind, TerrainParams},
+    player::PlayerSettings,
+    render::RenderSettings,
+};
+
+pub const SETTINGS_PATH: &str = "settings.toml";
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PreStartup, load_settings)
+            .add_systems(Last, save_settings);
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Settings {
+    pub render_distance_chunks: usize,
+    pub msaa_samples: u32,
+    pub shadows_enabled: bool,
+    pub fov_degrees: f32,
+    pub sensitivity: f32,
+    pub invert_y: bool,
+    pub screenshot_key: KeyCode,
+    pub screenshot_caption: bool,
+    /// Noise kinds blended into the terrain surface, with their weights.
+    pub noises: Vec<(NoiseKind, f64)>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self::from_resources(
+            &RenderSettings::default(),
+            &PlayerSettings::default(),
+            &TerrainParams::default(),
+        )
+    }
+}
+
+impl Settings {
+    pub fn from_resources(
+        render: &RenderSettings,
+        player: &PlayerSettings,
+        terrain: &TerrainParams,
+    ) -> Self {
+        Self {
+            render_distance_chunks: render.render_distance_chunks,
+            msaa_samples: render.msaa.samples(),
+            shadows_enabled: render.shadows_enabled,
+            fov_degrees: player.fov_degrees,
+            sensitivity: player.sensitivity,
+            invert_y: player.invert_y,
+            screenshot_key: render.screenshot_key,
+            screenshot_caption: render.screenshot_caption,
+            noises: terrain.noises.clone(),
+        }
+    }
+
+    pub fn apply(
+        &self,
+        render: &mut RenderSettings,
+        player: &mut PlayerSettings,
+        terrain: &mut TerrainParams,
+    ) {
+        render.render_distance_chunks = self.render_distance_chunks;
+        render.msaa = match self.msaa_samples {
+            1 => Msaa::Off,
+            2 => Msaa::Sample2,
+            4 => Msaa::Sample4,
+            8 => Msaa::Sample8,
+            samples => {
+                warn!("Unsupported msaa_samples {samples} in {SETTINGS_PATH}, using 4");
+                Msaa::Sample4
+            }
+        };
+        render.shadows_enabled = self.shadows_enabled;
+        render.screenshot_key = self.screenshot_key;
+        render.screenshot_caption = self.screenshot_caption;
+        player.fov_degrees = self.fov_degrees;
+        player.sensitivity = self.sensitivity;
+        player.invert_y = self.invert_y;
+        terrain.noises = self.noises.clone();
+    }
+}
+
+fn load_settings(
+    mut render: ResMut<RenderSettings>,
+    mut player: ResMut<PlayerSettings>,
+    mut terrain: ResMut<TerrainParams>,
+) {
+    let Ok(contents) = fs::read_to_string(SETTINGS_PATH) else {
+        return;
+    };
+
+    match toml::from_str::<Settings>(&contents) {
+        Ok(settings) => settings.apply(&mut render, &mut player, &mut terrain),
+        Err(err) => warn!("Ignoring invalid {SETTINGS_PATH}: {err}"),
+    }
+}
+
+fn save_settings(
+    render: Res<RenderSettings>,
+    player: Res<PlayerSettings>,
+    terrain: Res<TerrainParams>,
+) {
+    if render.is_added()
+        || player.is_added()
+        || terrain.is_added()
+        || !(render.is_changed() || player.is_changed() || terrain.is_changed())
+    {
+        return;
+    }
+
+    let settings = Settings::from_resources(&render, &player, &terrain);
+    let result = toml::to_string_pretty(&settings)
+        .map_err(|err| err.to_string())
+        .and_then(|contents| fs::write(SETTINGS_PATH, contents).map_err(|err| err.to_string()));
+    if let Err(err) = result {
+        error!("Failed to save {SETTINGS_PATH}: {err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noises_load_from_toml() {
+        let settings: Settings = toml::from_str("noises = [[\"Simplex\", 2.0]]").unwrap();
+        let mut terrain = TerrainParams::default();
+        settings.apply(
+            &mut RenderSettings::default(),
+            &mut PlayerSettings::default(),
+            &mut terrain,
+        );
+        assert_eq!(terrain.noises, vec![(NoiseKind::Simplex, 2.0)]);
+
+        let saved = toml::to_string_pretty(&Settings::default()).unwrap();
+        let reloaded: Settings = toml::from_str(&saved).unwrap();
+        assert_eq!(reloaded.noises, TerrainParams::default().noises);
+    }
+
+    #[test]
+    fn loaded_settings_apply_to_resources() {
+        let settings: Settings = toml::from_str(
+            r#"
+            render_distance_chunks = 3
+            sensitivity = 0.01
+            msaa_samples = 1
+            shadows_enabled = false
+            screenshot_key = "F12"
+            "#,
+        )
+        .unwrap();
+        let mut render = RenderSettings::default();
+        let mut player = PlayerSettings::default();
+        settings.apply(&mut render, &mut player, &mut TerrainParams::default());
+
+        assert_eq!(render.render_distance_chunks, 3);
+        assert_eq!(render.msaa, Msaa::Off);
+        assert!(!render.shadows_enabled);
+        assert_eq!(render.screenshot_key, KeyCode::F12);
+        assert_eq!(player.sensitivity, 0.01);
+        assert_eq!(player.fov_degrees, PlayerSettings::default().fov_degrees);
+    }
+
+    #[test]
+    fn unsupported_msaa_falls_back_to_4x() {
+        let settings: Settings = toml::from_str("msaa_samples = 3").unwrap();
+        let mut render = RenderSettings {
+            msaa: Msaa::Off,
+            ..default()
+        };
+        settings.apply(
+            &mut render,
+            &mut PlayerSettings::default(),
+            &mut TerrainParams::default(),
+        );
+        assert_eq!(render.msaa, Msaa::Sample4);
+    }
+}
@@ -0,0 +1,185 @@
+use std::f32::consts::TAU;
+
+use bevy::{pbr::CascadeShadowConfigBuilder, prelude::*};
+
+use crate::render::RenderSettings;
+
+pub const DAY_LENGTH_SECS: f32 = 600.0;
+pub const START_TIME_OF_DAY: f32 = 0.3;
+pub const FAST_FORWARD_SPEED: f32 = 60.0;
+pub const SUN_ILLUMINANCE: f32 = 10_000.0;
+pub const DAY_AMBIENT_BRIGHTNESS: f32 = 0.3;
+pub const NIGHT_AMBIENT_BRIGHTNESS: f32 = 0.02;
+pub const DAY_SKY: Color = Color::rgb(0.5, 0.7, 1.0);
+pub const NIGHT_SKY: Color = Color::rgb(0.02, 0.02, 0.08);
+pub const SUNSET_SKY: Color = Color::rgb(1.0, 0.5, 0.2);
+
+pub struct SkyPlugin;
+
+impl Plugin for SkyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TimeOfDay>()
+            .init_resource::<DayCycle>()
+            .add_systems(Startup, spawn_sun)
+            .add_systems(
+                Update,
+                ((advance_time, update_sky).chain(), apply_shadow_settings),
+            );
+    }
+}
+
+/// Fraction of the way through the day: 0.0 is midnight, 0.25 sunrise, 0.5 noon, 0.75 sunset.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct TimeOfDay(pub f32);
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        Self(START_TIME_OF_DAY)
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct DayCycle {
+    pub day_length_secs: f32,
+}
+
+impl Default for DayCycle {
+    fn default() -> Self {
+        Self {
+            day_length_secs: DAY_LENGTH_SECS,
+        }
+    }
+}
+
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Sun;
+
+fn spawn_sun(mut commands: Commands) {
+    // Directional shadows are fitted to the camera's view, so keeping the cascades short keeps
+    // the shadow detail around the player.
+    commands.spawn((
+        DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                shadows_enabled: true,
+                ..default()
+            },
+            cascade_shadow_config: CascadeShadowConfigBuilder {
+                first_cascade_far_bound: 16.0,
+                maximum_distance: 128.0,
+                ..default()
+            }
+            .into(),
+            ..default()
+        },
+        Sun,
+    ));
+}
+
+fn apply_shadow_settings(
+    settings: Res<RenderSettings>,
+    mut q_sun: Query<&mut DirectionalLight, With<Sun>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    for mut light in &mut q_sun {
+        light.shadows_enabled = settings.shadows_enabled;
+    }
+}
+
+fn advance_time(
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    cycle: Res<DayCycle>,
+    mut time_of_day: ResMut<TimeOfDay>,
+) {
+    let speed = if keys.pressed(KeyCode::F6) {
+        FAST_FORWARD_SPEED
+    } else {
+        1.0
+    };
+    time_of_day.0 =
+        (time_of_day.0 + time.delta_seconds() * speed / cycle.day_length_secs).rem_euclid(1.0);
+}
+
+fn update_sky(
+    time_of_day: Res<TimeOfDay>,
+    mut ambient: ResMut<AmbientLight>,
+    mut clear_color: ResMut<ClearColor>,
+    mut q_sun: Query<(&mut Transform, &mut DirectionalLight), With<Sun>>,
+) {
+    // The sun rises in the east (+X) and is tilted slightly south so it never points straight down.
+    let angle = (time_of_day.0 - 0.25) * TAU;
+    let sun_dir = Vec3::new(angle.cos(), angle.sin(), 0.2).normalize();
+    let elevation = sun_dir.y;
+    let daylight = (elevation * 2.0 + 0.5).clamp(0.0, 1.0);
+
+    let (mut transform, mut light) = q_sun.single_mut();
+    *transform = Transform::default().looking_to(-sun_dir, Vec3::Y);
+    light.illuminance = SUN_ILLUMINANCE * elevation.max(0.0);
+
+    ambient.brightness =
+        NIGHT_AMBIENT_BRIGHTNESS + (DAY_AMBIENT_BRIGHTNESS - NIGHT_AMBIENT_BRIGHTNESS) * daylight;
+
+    let sky = mix(NIGHT_SKY, DAY_SKY, daylight);
+    let horizon = 1.0 - (elevation.abs() * 4.0).min(1.0);
+    clear_color.0 = mix(sky, SUNSET_SKY, horizon * 0.6);
+}
+
+fn mix(a: Color, b: Color, t: f32) -> Color {
+    let [ar, ag, ab, aa] = a.as_rgba_f32();
+    let [br, bg, bb, ba] = b.as_rgba_f32();
+    Color::rgba(
+        ar + (br - ar) * t,
+        ag + (bg - ag) * t,
+        ab + (bb - ab) * t,
+        aa + (ba - aa) * t,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+    use std::time::Duration;
+
+    fn time_passed(keys: &[KeyCode]) -> f32 {
+        let mut world = World::new();
+        let mut time = Time::<()>::default();
+        time.advance_by(Duration::from_secs(1));
+        world.insert_resource(time);
+        let mut input = Input::<KeyCode>::default();
+        for &key in keys {
+            input.press(key);
+        }
+        world.insert_resource(input);
+        world.init_resource::<DayCycle>();
+        world.insert_resource(TimeOfDay(0.0));
+
+        world.run_system_once(advance_time);
+        world.resource::<TimeOfDay>().0
+    }
+
+    #[test]
+    fn shadow_setting_reaches_the_sun() {
+        let mut world = World::new();
+        world.insert_resource(RenderSettings {
+            shadows_enabled: false,
+            ..default()
+        });
+        world.run_system_once(spawn_sun);
+        world.run_system_once(apply_shadow_settings);
+
+        let mut q_sun = world.query_filtered::<&DirectionalLight, With<Sun>>();
+        assert!(!q_sun.single(&world).shadows_enabled);
+    }
+
+    #[test]
+    fn holding_f6_fast_forwards() {
+        let normal = time_passed(&[]);
+        let fast = time_passed(&[KeyCode::F6]);
+        assert!((normal - 1.0 / DAY_LENGTH_SECS).abs() < 1e-6);
+        assert!((fast - normal * FAST_FORWARD_SPEED).abs() < 1e-5);
+    }
+}
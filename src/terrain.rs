@@ -1,47 +1,1086 @@
-use bevy::{pbr::wireframe::Wireframe, prelude::*};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    f32::consts::{FRAC_PI_2, PI},
+    sync::Arc,
+};
+
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticId, Diagnostics, RegisterDiagnostic},
+    ecs::entity::Entities,
+    pbr::wireframe::Wireframe,
+    prelude::*,
+    tasks::{block_on, AsyncComputeTaskPool, Task, TaskPool, TaskPoolBuilder},
+    utils::Instant,
+};
+use futures_lite::future::poll_once;
+use image::{GrayImage, Luma};
 
 use crate::{
-    chunk::{Chunk, CHUNK_SIZE},
-    player::Player,
-    voxel::VOXEL_SIZE,
+    block::BlockRegistry,
+    chunk::{
+        Chunk, ChunkStorage, MeshPass, MeshingSettings, CHUNK_SIZE, CHUNK_SIZE_PADDED, MAX_LIGHT,
+        SEA_LEVEL, SURFACE_AMPLITUDE,
+    },
+    generation::{surface_heights, TerrainNoise, TerrainParams},
+    player::{move_player, Player, PlayerSpawn},
+    render::RenderSettings,
+    voxel::{Face, Voxel, VOXEL_SIZE},
 };
 
-pub const RENDER_DISTANCE_CHUNKS: usize = 8;
+pub const GEN_WORKER_THREADS: usize = 4;
+pub const MAX_GEN_TASKS: usize = 32;
+pub const MAX_CHUNK_SPAWNS_PER_FRAME: usize = 64;
+pub const MAX_CHUNK_INTEGRATIONS_PER_FRAME: usize = 16;
+pub const WORLD_SEED: u32 = 0;
+pub const WORLD_BORDER_CHUNKS: isize = 4;
+/// Height of the border walls, which are centered on y = 0. The clamp holds at any height.
+pub const WORLD_BORDER_WALL_HEIGHT: f32 = 1024.0;
+/// Directions skylight spreads in: sideways and down, never back up.
+const SKYLIGHT_SPREAD: [Face; 5] = [
+    Face::Left,
+    Face::Right,
+    Face::Bottom,
+    Face::Back,
+    Face::Front,
+];
+pub const CHUNK_MEMORY: DiagnosticId =
+    DiagnosticId::from_u128(0x3f0c_9b1e_57a4_4d2b_9e61_0c8a_d2f4_7b15);
 
 pub struct TerrainPlugin;
 
 impl Plugin for TerrainPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(Terrain::default())
-            .add_systems(Startup, generate_chunks)
-            .add_systems(Update, process_terrain);
+            .init_resource::<TerrainParams>()
+            .init_resource::<GenWorkerPool>()
+            .init_resource::<HeightmapExport>()
+            .init_resource::<WorldBorder>()
+            .init_resource::<MeshingSettings>()
+            .init_resource::<TerrainDebug>()
+            .init_resource::<ChunkMeshTasks>()
+            .init_resource::<ChunkSpawnSettings>()
+            .init_resource::<ChunkGenSettings>()
+            .register_diagnostic(Diagnostic::new(CHUNK_MEMORY, "chunk_memory_mib", 20))
+            .add_systems(
+                Startup,
+                (queue_chunks, create_chunk_material, spawn_world_border),
+            )
+            .add_systems(
+                Update,
+                (
+                    (
+                        spawn_gen_tasks,
+                        poll_gen_tasks,
+                        process_terrain,
+                        spawn_chunk_meshes,
+                    )
+                        .chain(),
+                    (toggle_flat_lighting, apply_flat_lighting).chain(),
+                    export_heightmap,
+                    clamp_to_world_border.after(move_player),
+                    check_duplicate_chunks,
+                    (toggle_memory_measurement, measure_chunk_memory).chain(),
+                    log_chunk_histogram,
+                ),
+            );
     }
 }
 
 #[derive(Resource, Clone, Default)]
 pub struct Terrain {
     pub chunks: Vec<Chunk>,
+    /// Position in `chunks` of the chunk at each coordinate; add chunks through `insert` so it
+    /// stays in sync.
+    index: HashMap<[isize; 3], usize>,
+    /// Lowest and highest chunk Y of the loaded chunks.
+    y_bounds: Option<(isize, isize)>,
+    /// Generator of the chunks that aren't loaded yet, which column tops count in.
+    noise: Option<Arc<TerrainNoise>>,
+    /// Voxel edits made so far, which tell when a `GenNeighborhood` has gone stale.
+    edits: u64,
 }
 
-fn generate_chunks(mut terrain: ResMut<Terrain>) {
-    for i in -4..=4 {
-        for j in -4..=4 {
-            for k in -4..=4 {
-                terrain.chunks.push(Chunk::new(0, i, j, k));
+impl Terrain {
+    pub fn new(noise: Arc<TerrainNoise>) -> Self {
+        Self {
+            noise: Some(noise),
+            ..default()
+        }
+    }
+
+    pub fn insert(&mut self, chunk: Chunk) {
+        self.index.insert(
+            [chunk.chunk_x, chunk.chunk_y, chunk.chunk_z],
+            self.chunks.len(),
+        );
+        self.y_bounds = Some(
+            self.y_bounds
+                .map_or((chunk.chunk_y, chunk.chunk_y), |(min, max)| {
+                    (min.min(chunk.chunk_y), max.max(chunk.chunk_y))
+                }),
+        );
+        self.chunks.push(chunk);
+    }
+
+    /// What a chunk generated at `coords` needs from the loaded chunks around it, for
+    /// `GenNeighborhood::generate` to use off the main thread.
+    pub fn neighborhood(&self, coords: [isize; 3]) -> GenNeighborhood {
+        let mut column_tops = Vec::new();
+        let mut storages = Vec::new();
+        for dx in -1..=1 {
+            for dz in -1..=1 {
+                // Every chunk in a column holds the same column tops, so any loaded one will do.
+                if let Some(neighbor) = self
+                    .loaded_ys()
+                    .find_map(|y| self.chunk([coords[0] + dx, y, coords[2] + dz]))
+                {
+                    column_tops.push(([dx, dz], neighbor.column_tops.clone()));
+                }
+
+                for dy in -1..=1 {
+                    let offset = [dx, dy, dz];
+                    if offset == [0; 3] {
+                        continue;
+                    }
+                    if let Some(neighbor) = self.chunk(Self::offset_coords(coords, offset)) {
+                        storages.push((offset, neighbor.storage.clone()));
+                    }
+                }
+            }
+        }
+
+        GenNeighborhood {
+            coords,
+            edits: self.edits,
+            column_tops,
+            storages,
+        }
+    }
+
+    /// Adds a chunk from `GenNeighborhood::generate`, whose column tops and padding voxels are
+    /// already up to date, then spreads light between it and the loaded chunks around it. The
+    /// neighborhood it was generated in mustn't have gone stale. Light keeps changing as chunks
+    /// load, so this part stays on the main thread, under `ChunkGenSettings::max_per_frame`.
+    pub fn insert_generated(&mut self, generated: GeneratedChunk, registry: &BlockRegistry) {
+        let GeneratedChunk {
+            mut chunk, shaded, ..
+        } = generated;
+        let coords = [chunk.chunk_x, chunk.chunk_y, chunk.chunk_z];
+        let origin = coords.map(|c| c * CHUNK_SIZE as isize);
+        let neighbors = (0..27)
+            .map(|i| [i / 9, i / 3 % 3, i % 3].map(|d| d - 1))
+            .filter(|&offset| offset != [0; 3])
+            .filter(|offset| self.chunk(Self::offset_coords(coords, *offset)).is_some())
+            .collect::<Vec<_>>();
+
+        // Light flows in through the padding, taken from the outer layers of the neighbors. Few
+        // voxels have block light, so only the lit ones are looked at.
+        let padded_half = CHUNK_SIZE_PADDED as isize / 2;
+        let half = CHUNK_SIZE as isize / 2;
+        let mut sky = VecDeque::new();
+        let mut lit = VecDeque::new();
+        for &offset in &neighbors {
+            let neighbor = &self.chunks[self.index[&Self::offset_coords(coords, offset)]];
+            for pos in border_slab(offset, true) {
+                let level = neighbor.skylight.get(Self::offset_local(pos, offset));
+                chunk.skylight.set(pos, level);
+                if level > 0 {
+                    sky.push_back([0, 1, 2].map(|axis| pos[axis] + origin[axis]));
+                }
+            }
+
+            for (&local, &light) in &neighbor.block_light {
+                let pos = Self::offset_local(local, offset.map(|d| -d));
+                if local.iter().all(|l| (-half..half).contains(l))
+                    && pos.iter().all(|p| (-padded_half..padded_half).contains(p))
+                {
+                    chunk.block_light.insert(pos, light);
+                    lit.push_back([0, 1, 2].map(|axis| pos[axis] + origin[axis]));
+                }
+            }
+        }
+
+        chunk.dirty = true;
+        self.insert(chunk);
+
+        // And out through the chunk's outer layer, whose copies in the padding of the neighbors
+        // were only the generator's guess until now.
+        let ours = self.index[&coords];
+        for offset in neighbors {
+            let levels = border_slab(offset, false)
+                .map(|pos| (pos, self.chunks[ours].skylight.get(pos)))
+                .collect::<Vec<_>>();
+            let neighbor = self.chunk_mut(Self::offset_coords(coords, offset)).unwrap();
+            for (pos, level) in levels {
+                let local = Self::offset_local(pos, offset);
+                if neighbor.skylight.get(local) != level {
+                    neighbor.skylight.set(local, level);
+                    neighbor.dirty = true;
+                }
+                if level > 0 {
+                    sky.push_back([0, 1, 2].map(|axis| pos[axis] + origin[axis]));
+                }
+            }
+        }
+        for pos in shaded {
+            for face in Face::ALL {
+                let [dx, dy, dz] = face.offset();
+                let neighbor = [pos[0] + dx, pos[1] + dy, pos[2] + dz];
+                if self.skylight(neighbor) > 0 {
+                    sky.push_back(neighbor);
+                }
+            }
+        }
+
+        self.relight_sky(sky, registry);
+        self.relight(lit, registry);
+    }
+
+    /// Coordinates of the chunk at `offset` from the one at `coords`.
+    fn offset_coords(coords: [isize; 3], offset: [isize; 3]) -> [isize; 3] {
+        [0, 1, 2].map(|axis| coords[axis] + offset[axis])
+    }
+
+    /// Where local `pos` of a chunk sits in the chunk at `offset` from it.
+    fn offset_local(pos: [isize; 3], offset: [isize; 3]) -> [isize; 3] {
+        [0, 1, 2].map(|axis| pos[axis] - offset[axis] * CHUNK_SIZE as isize)
+    }
+
+    /// Chunk Y coordinates that loaded chunks can have.
+    fn loaded_ys(&self) -> impl DoubleEndedIterator<Item = isize> {
+        self.y_bounds.into_iter().flat_map(|(min, max)| min..=max)
+    }
+
+    pub fn chunk(&self, coords: [isize; 3]) -> Option<&Chunk> {
+        self.index.get(&coords).map(|&i| &self.chunks[i])
+    }
+
+    pub fn chunk_mut(&mut self, coords: [isize; 3]) -> Option<&mut Chunk> {
+        self.index.get(&coords).map(|&i| &mut self.chunks[i])
+    }
+
+    pub fn voxel(&self, world: [isize; 3]) -> Option<Voxel> {
+        let (chunk, local) = Chunk::voxel_coords(world);
+        self.chunk(chunk)?.storage.get(&local).copied()
+    }
+
+    /// Sets the voxel at `world` in its chunk and in the padding of any neighbors that hold a
+    /// copy of it, marking each of them dirty, and updates the light around it.
+    pub fn set_voxel(&mut self, world: [isize; 3], voxel: Option<Voxel>, registry: &BlockRegistry) {
+        self.edits += 1;
+        let old_light = self.block_light(world);
+        for (coords, local) in Self::padded_copies(world) {
+            if let Some(chunk) = self.chunk_mut(coords) {
+                Arc::make_mut(&mut chunk.storage).set(local, voxel);
+                chunk.dirty = true;
             }
         }
+
+        let (old_top, new_top) = self.update_column_top(world[0], world[2], registry);
+        self.update_skylight(world, old_top, new_top, registry);
+        self.update_block_light(world, old_light, registry);
+    }
+
+    /// Removes every solid voxel centered within `radius` of `center`, both in world units, and
+    /// returns how many went. Liquids are left to fill the crater. Edits only mark the chunks
+    /// they touch dirty, so each of them is remeshed once however many of its voxels go.
+    pub fn explode(&mut self, center: Vec3, radius: f32, registry: &BlockRegistry) -> usize {
+        let center = (center / VOXEL_SIZE).to_array();
+        let radius = radius / VOXEL_SIZE;
+        let min = center.map(|c| (c - radius).ceil() as isize);
+        let max = center.map(|c| (c + radius).floor() as isize);
+
+        let mut removed = 0;
+        for x in min[0]..=max[0] {
+            for z in min[2]..=max[2] {
+                // Top down, so each removal only lowers its column's top by the one voxel.
+                for y in (min[1]..=max[1]).rev() {
+                    let offset = [x, y, z]
+                        .iter()
+                        .zip(center)
+                        .map(|(&p, c)| (p as f32 - c).powi(2))
+                        .sum::<f32>();
+                    if offset > radius * radius {
+                        continue;
+                    }
+                    if self
+                        .voxel([x, y, z])
+                        .is_some_and(|voxel| !registry.is_liquid(&voxel))
+                    {
+                        self.set_voxel([x, y, z], None, registry);
+                        removed += 1;
+                    }
+                }
+            }
+        }
+        removed
+    }
+
+    /// Coordinates of every chunk whose padded volume holds `world`, loaded or not, along with
+    /// its local position there.
+    fn padded_copies(world: [isize; 3]) -> impl Iterator<Item = ([isize; 3], [isize; 3])> {
+        let (owner, _) = Chunk::voxel_coords(world);
+        let half = CHUNK_SIZE_PADDED as isize / 2;
+        (0..27).filter_map(move |i| {
+            let coords = [i / 9, i / 3 % 3, i % 3].map(|d| d - 1);
+            let coords = [0, 1, 2].map(|axis| owner[axis] + coords[axis]);
+            let local = [0, 1, 2].map(|axis| world[axis] - coords[axis] * CHUNK_SIZE as isize);
+            local
+                .iter()
+                .all(|l| (-half..half).contains(l))
+                .then_some((coords, local))
+        })
+    }
+
+    /// Skylight at `world`, zero where no chunk is loaded.
+    pub fn skylight(&self, world: [isize; 3]) -> u8 {
+        let (chunk, local) = Chunk::voxel_coords(world);
+        self.chunk(chunk)
+            .map_or(0, |chunk| chunk.skylight.get(local))
+    }
+
+    /// Sets the skylight at `world` in every chunk holding it, marking those it changes dirty.
+    fn set_skylight(&mut self, world: [isize; 3], level: u8) {
+        for (coords, local) in Self::padded_copies(world) {
+            if let Some(chunk) = self.chunk_mut(coords) {
+                if chunk.skylight.get(local) != level {
+                    chunk.skylight.set(local, level);
+                    chunk.dirty = true;
+                }
+            }
+        }
+    }
+
+    /// Fixes up skylight after the voxel at `world` changed, moving its column's top from
+    /// `old_top` to `new_top`. The voxels between the two tops and `world` itself are the only
+    /// ones that can gain or lose the sky; from there the unlight and relight passes work as in
+    /// `update_block_light`, except that skylight never spreads up.
+    fn update_skylight(
+        &mut self,
+        world: [isize; 3],
+        old_top: isize,
+        new_top: isize,
+        registry: &BlockRegistry,
+    ) {
+        let Some((min_chunk_y, max_chunk_y)) = self.y_bounds else {
+            return;
+        };
+        let half = CHUNK_SIZE as isize / 2;
+        let min_y = min_chunk_y * CHUNK_SIZE as isize - half;
+        let max_y = max_chunk_y * CHUNK_SIZE as isize + half - 1;
+        let column =
+            old_top.min(new_top).saturating_add(1).max(min_y)..=old_top.max(new_top).min(max_y);
+
+        let mut unlight = VecDeque::new();
+        let mut relight = VecDeque::new();
+        for pos in column.map(|y| [world[0], y, world[2]]).chain([world]) {
+            if self.chunk(Chunk::voxel_coords(pos).0).is_none() {
+                continue;
+            }
+
+            let light = self.skylight(pos);
+            if pos[1] > new_top && !self.blocks_light(pos, registry) {
+                if light < MAX_LIGHT {
+                    self.set_skylight(pos, MAX_LIGHT);
+                    relight.push_back(pos);
+                }
+            } else {
+                self.set_skylight(pos, 0);
+                unlight.push_back((pos, light));
+            }
+        }
+
+        while let Some((pos, level)) = unlight.pop_front() {
+            for face in Face::ALL {
+                let [dx, dy, dz] = face.offset();
+                let neighbor = [pos[0] + dx, pos[1] + dy, pos[2] + dz];
+                let light = self.skylight(neighbor);
+                if light == 0 {
+                    continue;
+                }
+
+                // The voxel above can't have been lit from this one, only light it again.
+                if face != Face::Top && light < level {
+                    self.set_skylight(neighbor, 0);
+                    unlight.push_back((neighbor, light));
+                } else {
+                    relight.push_back(neighbor);
+                }
+            }
+        }
+
+        self.relight_sky(relight, registry);
+    }
+
+    /// Floods skylight sideways and down from each voxel in `queue`, losing one level per step.
+    fn relight_sky(&mut self, mut queue: VecDeque<[isize; 3]>, registry: &BlockRegistry) {
+        while let Some(pos) = queue.pop_front() {
+            let next = self.skylight(pos).saturating_sub(1);
+            for face in SKYLIGHT_SPREAD {
+                let [dx, dy, dz] = face.offset();
+                let neighbor = [pos[0] + dx, pos[1] + dy, pos[2] + dz];
+                if next > self.skylight(neighbor) && !self.blocks_light(neighbor, registry) {
+                    self.set_skylight(neighbor, next);
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+    }
+
+    /// Block light at `world`, zero where no chunk is loaded.
+    pub fn block_light(&self, world: [isize; 3]) -> u8 {
+        let (chunk, local) = Chunk::voxel_coords(world);
+        self.chunk(chunk)
+            .and_then(|chunk| chunk.block_light.get(&local).copied())
+            .unwrap_or(0)
+    }
+
+    /// Sets the block light at `world` in every chunk holding it, marking those it changes dirty.
+    fn set_block_light(&mut self, world: [isize; 3], level: u8) {
+        for (coords, local) in Self::padded_copies(world) {
+            if let Some(chunk) = self.chunk_mut(coords) {
+                let old = if level > 0 {
+                    chunk.block_light.insert(local, level)
+                } else {
+                    chunk.block_light.remove(&local)
+                };
+                if old.unwrap_or(0) != level {
+                    chunk.dirty = true;
+                }
+            }
+        }
+    }
+
+    /// Whether light stops at `world`: opaque voxels and chunks that aren't loaded.
+    fn blocks_light(&self, world: [isize; 3], registry: &BlockRegistry) -> bool {
+        let (chunk, local) = Chunk::voxel_coords(world);
+        self.chunk(chunk).is_none_or(|chunk| {
+            chunk
+                .storage
+                .get(&local)
+                .is_some_and(|voxel| registry.is_opaque(voxel))
+        })
+    }
+
+    /// Fixes up block light after the voxel at `world`, which had `old_light`, changed. The
+    /// unlight pass darkens everything that got its light through `world`, collecting the lit
+    /// voxels bordering the darkened area; the relight pass then floods back in from those and
+    /// from any emitters, including a new one at `world`.
+    fn update_block_light(&mut self, world: [isize; 3], old_light: u8, registry: &BlockRegistry) {
+        let mut unlight = VecDeque::new();
+        let mut relight = VecDeque::new();
+        if old_light > 0 {
+            self.set_block_light(world, 0);
+            unlight.push_back((world, old_light));
+        }
+
+        while let Some((pos, level)) = unlight.pop_front() {
+            for face in Face::ALL {
+                let [dx, dy, dz] = face.offset();
+                let neighbor = [pos[0] + dx, pos[1] + dy, pos[2] + dz];
+                let light = self.block_light(neighbor);
+                if light == 0 {
+                    continue;
+                }
+
+                if light < level {
+                    self.set_block_light(neighbor, 0);
+                    unlight.push_back((neighbor, light));
+                } else {
+                    relight.push_back(neighbor);
+                }
+            }
+
+            let emission = self.voxel(pos).map_or(0, |voxel| registry.emission(&voxel));
+            if emission > 0 {
+                self.set_block_light(pos, emission);
+                relight.push_back(pos);
+            }
+        }
+
+        let emission = self
+            .voxel(world)
+            .map_or(0, |voxel| registry.emission(&voxel));
+        if emission > self.block_light(world) {
+            self.set_block_light(world, emission);
+            relight.push_back(world);
+        }
+
+        // A voxel that lets light through again gets filled in from its lit neighbors.
+        if !self.blocks_light(world, registry) {
+            for face in Face::ALL {
+                let [dx, dy, dz] = face.offset();
+                let neighbor = [world[0] + dx, world[1] + dy, world[2] + dz];
+                if self.block_light(neighbor) > 0 {
+                    relight.push_back(neighbor);
+                }
+            }
+        }
+
+        self.relight(relight, registry);
+    }
+
+    /// Floods block light outwards from each voxel in `queue`, losing one level per step.
+    fn relight(&mut self, mut queue: VecDeque<[isize; 3]>, registry: &BlockRegistry) {
+        while let Some(pos) = queue.pop_front() {
+            let next = self.block_light(pos).saturating_sub(1);
+            for face in Face::ALL {
+                let [dx, dy, dz] = face.offset();
+                let neighbor = [pos[0] + dx, pos[1] + dy, pos[2] + dz];
+                if next > self.block_light(neighbor) && !self.blocks_light(neighbor, registry) {
+                    self.set_block_light(neighbor, next);
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+    }
+
+    /// Steps through the voxels along a ray (Amanatides & Woo), starting with the one `origin` is
+    /// in, and returns the first non-liquid one within `reach`, along with the face the ray
+    /// entered it through.
+    pub fn raycast(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        reach: f32,
+        registry: &BlockRegistry,
+    ) -> Option<([isize; 3], Face)> {
+        let direction = direction.normalize_or_zero().to_array();
+        // Voxels are centered on integer coordinates, so shift by half a voxel to put the cell
+        // boundaries on integers.
+        let start = (origin / VOXEL_SIZE + 0.5).to_array();
+        let reach = reach / VOXEL_SIZE;
+
+        let mut cell = start.map(|s| s.floor() as isize);
+        let step = direction.map(|d| d.signum() as isize * (d != 0.0) as isize);
+        let t_delta = direction.map(|d| 1.0 / d.abs());
+        let mut t_max = [0, 1, 2].map(|axis| {
+            let d = direction[axis];
+            if d > 0.0 {
+                (cell[axis] as f32 + 1.0 - start[axis]) / d
+            } else if d < 0.0 {
+                (start[axis] - cell[axis] as f32) / -d
+            } else {
+                f32::INFINITY
+            }
+        });
+
+        let targetable = |cell: [isize; 3]| {
+            self.voxel(cell)
+                .is_some_and(|voxel| !registry.is_liquid(&voxel))
+        };
+
+        // Starting inside a block hits that block, on the face opposite the one the ray leaves by.
+        if targetable(cell) {
+            let axis = (0..3).min_by(|&a, &b| t_max[a].total_cmp(&t_max[b]))?;
+            let mut normal = [0.0; 3];
+            normal[axis] = step[axis] as f32;
+            return Some((cell, Face::from_normal(normal)?.opposite()));
+        }
+
+        loop {
+            let axis = (0..3).min_by(|&a, &b| t_max[a].total_cmp(&t_max[b]))?;
+            if t_max[axis] > reach {
+                return None;
+            }
+
+            cell[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+
+            if targetable(cell) {
+                let mut normal = [0.0; 3];
+                normal[axis] = -step[axis] as f32;
+                return Some((cell, Face::from_normal(normal)?));
+            }
+        }
+    }
+
+    /// World Y of the highest voxel of any kind in the column at `x`, `z`, if any loaded chunk
+    /// has one.
+    pub fn highest_voxel(&self, x: isize, z: isize) -> Option<isize> {
+        self.highest_matching(x, z, |_| true)
+    }
+
+    /// World Y of the highest solid voxel in the column at `x`, `z`, if any loaded chunk has one.
+    /// Liquids don't count, so the surface under a lake is its bed.
+    pub fn highest_solid(&self, x: isize, z: isize, registry: &BlockRegistry) -> Option<isize> {
+        self.highest_matching(x, z, |voxel| !registry.is_liquid(voxel))
+    }
+
+    fn highest_matching(
+        &self,
+        x: isize,
+        z: isize,
+        matches: impl Fn(&Voxel) -> bool,
+    ) -> Option<isize> {
+        let ([chunk_x, _, chunk_z], [local_x, _, local_z]) = Chunk::voxel_coords([x, 0, z]);
+        let half = CHUNK_SIZE as isize / 2;
+
+        // Searching from the top chunk down, the first match is the highest.
+        self.loaded_ys().rev().find_map(|chunk_y| {
+            let chunk = self.chunk([chunk_x, chunk_y, chunk_z])?;
+            (-half..half)
+                .rev()
+                .find(|&y| {
+                    chunk
+                        .storage
+                        .get(&[local_x, y, local_z])
+                        .is_some_and(&matches)
+                })
+                .map(|y| chunk_y * CHUNK_SIZE as isize + y)
+        })
+    }
+
+    /// Coordinates held by more than one chunk, once for each extra copy.
+    pub fn duplicate_chunks(&self) -> Vec<[isize; 3]> {
+        let mut seen = HashSet::new();
+        self.chunks
+            .iter()
+            .map(|chunk| [chunk.chunk_x, chunk.chunk_y, chunk.chunk_z])
+            .filter(|&coords| !seen.insert(coords))
+            .collect()
+    }
+
+    /// World Y of the top opaque voxel in the column at `x`, `z`, or `isize::MIN` if it has none.
+    /// Chunks that aren't loaded yet count as the generator will make them, so an edit under one
+    /// doesn't lose the surface it holds.
+    fn column_top(&self, x: isize, z: isize, registry: &BlockRegistry) -> isize {
+        let loaded = self.highest_matching(x, z, |voxel| registry.is_opaque(voxel));
+        let generated = self.noise.as_ref().and_then(|noise| {
+            let surface = surface_heights(noise, x, z, 1, 1)[0];
+            let ([chunk_x, surface_chunk, chunk_z], _) = Chunk::voxel_coords([x, surface, z]);
+            // Below the surface, an unloaded chunk is solid right up to its top.
+            (isize::MIN..=surface_chunk)
+                .rev()
+                .find(|&chunk_y| self.chunk([chunk_x, chunk_y, chunk_z]).is_none())
+                .map(|chunk_y| {
+                    surface.min(chunk_y * CHUNK_SIZE as isize + CHUNK_SIZE as isize / 2 - 1)
+                })
+        });
+        loaded.max(generated).unwrap_or(isize::MIN)
+    }
+
+    /// Recomputes the top opaque voxel of the column at `x`, `z` after an edit and stores it in
+    /// every chunk holding the column, returning the old and new tops.
+    fn update_column_top(
+        &mut self,
+        x: isize,
+        z: isize,
+        registry: &BlockRegistry,
+    ) -> (isize, isize) {
+        let top = self.column_top(x, z, registry);
+        let mut old = top;
+
+        // The column sits in the padding of up to two chunks along each of X and Z.
+        let padded_half = CHUNK_SIZE_PADDED as isize / 2;
+        let ([owner_x, _, owner_z], _) = Chunk::voxel_coords([x, 0, z]);
+        let holders = |owner: isize, p: isize| {
+            (owner - 1..=owner + 1).filter(move |c| {
+                (-padded_half..padded_half).contains(&(p - c * CHUNK_SIZE as isize))
+            })
+        };
+
+        for chunk_x in holders(owner_x, x) {
+            for chunk_z in holders(owner_z, z) {
+                for chunk_y in self.loaded_ys() {
+                    let Some(chunk) = self.chunk_mut([chunk_x, chunk_y, chunk_z]) else {
+                        continue;
+                    };
+
+                    let local_x = x - chunk_x * CHUNK_SIZE as isize;
+                    let local_z = z - chunk_z * CHUNK_SIZE as isize;
+                    let column = &mut chunk.column_tops[Chunk::column_index(local_x, local_z)];
+                    old = *column;
+                    *column = top;
+                }
+            }
+        }
+        (old, top)
     }
 }
 
-fn process_terrain(
+#[derive(Resource, Clone, Debug)]
+pub struct ChunkMaterial {
+    pub opaque: Handle<StandardMaterial>,
+    pub transparent: Handle<StandardMaterial>,
+}
+
+/// Meshing tasks in flight on `AsyncComputeTaskPool`, each yielding a chunk's opaque and
+/// transparent meshes.
+#[derive(Resource, Default)]
+pub struct ChunkMeshTasks(pub HashMap<[isize; 3], Task<(Mesh, Mesh)>>);
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct ChunkSpawnSettings {
+    /// Most finished mesh tasks turned into entities per frame; the rest wait for later frames.
+    pub max_per_frame: usize,
+}
+
+impl Default for ChunkSpawnSettings {
+    fn default() -> Self {
+        Self {
+            max_per_frame: MAX_CHUNK_SPAWNS_PER_FRAME,
+        }
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct ChunkGenSettings {
+    /// Most finished generation tasks added to the terrain per frame; the rest wait for later
+    /// frames.
+    pub max_per_frame: usize,
+}
+
+impl Default for ChunkGenSettings {
+    fn default() -> Self {
+        Self {
+            max_per_frame: MAX_CHUNK_INTEGRATIONS_PER_FRAME,
+        }
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct TerrainDebug {
+    pub check_duplicates: bool,
+    /// Records `CHUNK_MEMORY`, which gets logged once a second. Toggled with F8.
+    pub measure_memory: bool,
+}
+
+impl Default for TerrainDebug {
+    fn default() -> Self {
+        Self {
+            check_duplicates: cfg!(debug_assertions),
+            measure_memory: false,
+        }
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct WorldBorder {
+    pub radius_chunks: isize,
+}
+
+impl Default for WorldBorder {
+    fn default() -> Self {
+        Self {
+            radius_chunks: WORLD_BORDER_CHUNKS,
+        }
+    }
+}
+
+impl WorldBorder {
+    pub fn extent(&self) -> f32 {
+        (self.radius_chunks as f32 + 0.5) * CHUNK_SIZE as f32 * VOXEL_SIZE
+    }
+}
+
+#[derive(Component, Clone, Copy, Debug)]
+pub struct BorderWall;
+
+/// Thread pool chunk generation runs on, kept apart from Bevy's pools. Insert
+/// `GenWorkerPool::new(n)` before adding `TerrainPlugin` to change the thread count.
+#[derive(Resource)]
+pub struct GenWorkerPool(pub TaskPool);
+
+impl GenWorkerPool {
+    pub fn new(num_threads: usize) -> Self {
+        Self(
+            TaskPoolBuilder::new()
+                .num_threads(num_threads)
+                .thread_name("Chunk Generation".to_string())
+                .build(),
+        )
+    }
+}
+
+impl Default for GenWorkerPool {
+    fn default() -> Self {
+        Self::new(GEN_WORKER_THREADS)
+    }
+}
+
+/// Chunks waiting to be generated, and the generation tasks in flight on `GenWorkerPool`.
+#[derive(Resource)]
+pub struct ChunkGenQueue {
+    pub noise: Arc<TerrainNoise>,
+    pub pending: VecDeque<[isize; 3]>,
+    pub tasks: Vec<Task<GeneratedChunk>>,
+}
+
+/// The parts of the chunks loaded around a chunk about to be generated that edits can make
+/// differ from the generator: their voxels and column tops. It goes stale with the next edit.
+pub struct GenNeighborhood {
+    coords: [isize; 3],
+    edits: u64,
+    /// Column tops of each loaded neighboring column, by X and Z offset.
+    column_tops: Vec<([isize; 2], Vec<isize>)>,
+    /// Voxels of each loaded neighbor, by offset.
+    storages: Vec<([isize; 3], Arc<ChunkStorage>)>,
+}
+
+impl GenNeighborhood {
+    /// Generates the chunk and brings its column tops, the skylight they shade and its padding
+    /// voxels up to date with the neighbors, ready for `Terrain::insert_generated`.
+    pub fn generate(&self, noise: &TerrainNoise) -> GeneratedChunk {
+        let [chunk_x, chunk_y, chunk_z] = self.coords;
+        let mut chunk = Chunk::new(noise, chunk_x, chunk_y, chunk_z);
+        let origin = self.coords.map(|c| c * CHUNK_SIZE as isize);
+        let padded_half = CHUNK_SIZE_PADDED as isize / 2;
+
+        let generated_tops = chunk.column_tops.clone();
+        for ([dx, dz], tops) in &self.column_tops {
+            for x in -padded_half..padded_half {
+                for z in -padded_half..padded_half {
+                    let local_x = x - dx * CHUNK_SIZE as isize;
+                    let local_z = z - dz * CHUNK_SIZE as isize;
+                    if (-padded_half..padded_half).contains(&local_x)
+                        && (-padded_half..padded_half).contains(&local_z)
+                    {
+                        chunk.column_tops[Chunk::column_index(x, z)] =
+                            tops[Chunk::column_index(local_x, local_z)];
+                    }
+                }
+            }
+        }
+
+        // Voxels built above the surface shade the air the generator left open to the sky.
+        let mut shaded = Vec::new();
+        for x in -padded_half..padded_half {
+            for z in -padded_half..padded_half {
+                let column = Chunk::column_index(x, z);
+                let shade = generated_tops[column] + 1..=chunk.column_tops[column];
+                for y in -padded_half..padded_half {
+                    if shade.contains(&(origin[1] + y)) {
+                        chunk.skylight.set([x, y, z], 0);
+                        shaded.push([x + origin[0], y + origin[1], z + origin[2]]);
+                    }
+                }
+            }
+        }
+
+        for (offset, storage) in &self.storages {
+            for pos in border_slab(*offset, true) {
+                let voxel = storage.get(&Terrain::offset_local(pos, *offset)).copied();
+                if chunk.storage.get(&pos).copied() != voxel {
+                    Arc::make_mut(&mut chunk.storage).set(pos, voxel);
+                }
+            }
+        }
+        Arc::make_mut(&mut chunk.storage).compact();
+
+        GeneratedChunk {
+            chunk,
+            shaded,
+            edits: self.edits,
+        }
+    }
+}
+
+/// A chunk from `GenNeighborhood::generate`, along with the voxels its column tops shaded.
+pub struct GeneratedChunk {
+    chunk: Chunk,
+    shaded: Vec<[isize; 3]>,
+    edits: u64,
+}
+
+/// Local positions in the layer of a chunk facing its neighbor at `offset`: the padding the
+/// neighbor owns when `padding`, otherwise the chunk's own voxels the neighbor pads with.
+fn border_slab(offset: [isize; 3], padding: bool) -> impl Iterator<Item = [isize; 3]> {
+    let half = CHUNK_SIZE as isize / 2;
+    let [xs, ys, zs] = offset.map(|d| match (d, padding) {
+        (-1, true) => -half - 1..-half,
+        (-1, false) => -half..-half + 1,
+        (1, true) => half..half + 1,
+        (1, false) => half - 1..half,
+        _ => -half..half,
+    });
+    xs.flat_map(move |x| {
+        let zs = zs.clone();
+        ys.clone()
+            .flat_map(move |y| zs.clone().map(move |z| [x, y, z]))
+    })
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct HeightmapExport {
+    pub min: IVec2,
+    pub size: UVec2,
+    pub path: String,
+}
+
+impl Default for HeightmapExport {
+    fn default() -> Self {
+        Self {
+            min: IVec2::splat(-256),
+            size: UVec2::splat(512),
+            path: "heightmap.png".to_string(),
+        }
+    }
+}
+
+impl HeightmapExport {
+    pub fn to_image(&self, noise: &TerrainNoise) -> GrayImage {
+        let lowest = SEA_LEVEL as f64 - SURFACE_AMPLITUDE;
+        let heights = surface_heights(
+            noise,
+            self.min.x as isize,
+            self.min.y as isize,
+            self.size.x as usize,
+            self.size.y as usize,
+        );
+
+        GrayImage::from_fn(self.size.x, self.size.y, |px, pz| {
+            let height = heights[(pz * self.size.x + px) as usize] as f64;
+            let brightness = (height - lowest) / (SURFACE_AMPLITUDE * 2.0) * 255.0;
+            Luma([brightness.clamp(0.0, 255.0) as u8])
+        })
+    }
+}
+
+fn queue_chunks(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
+    mut terrain: ResMut<Terrain>,
+    params: Res<TerrainParams>,
+    border: Res<WorldBorder>,
+    spawn: Res<PlayerSpawn>,
+) {
+    let r = border.radius_chunks;
+    let mut pending = Vec::new();
+    for i in -r..=r {
+        for j in -r..=r {
+            for k in -r..=r {
+                pending.push([i, j, k]);
+            }
+        }
+    }
+
+    // Generate outwards from the spawn point so the chunks around the player show up first.
+    let origin = Chunk::coords_at(spawn.position);
+    pending.sort_by_key(|coords| {
+        (0..3)
+            .map(|axis| (coords[axis] - origin[axis]).pow(2))
+            .sum::<isize>()
+    });
+
+    let noise = Arc::new(TerrainNoise::new(WORLD_SEED, &params));
+    *terrain = Terrain::new(noise.clone());
+    commands.insert_resource(ChunkGenQueue {
+        noise,
+        pending: pending.into(),
+        tasks: Vec::new(),
+    });
+}
+
+fn spawn_gen_tasks(
+    mut queue: ResMut<ChunkGenQueue>,
+    pool: Res<GenWorkerPool>,
+    terrain: Res<Terrain>,
+) {
+    while queue.tasks.len() < MAX_GEN_TASKS {
+        let Some(coords) = queue.pending.pop_front() else {
+            break;
+        };
+
+        let noise = queue.noise.clone();
+        let neighborhood = terrain.neighborhood(coords);
+        let task = pool.0.spawn(async move { neighborhood.generate(&noise) });
+        queue.tasks.push(task);
+    }
+}
+
+fn poll_gen_tasks(
+    mut queue: ResMut<ChunkGenQueue>,
+    settings: Res<ChunkGenSettings>,
+    registry: Res<BlockRegistry>,
+    mut terrain: ResMut<Terrain>,
+) {
+    let ChunkGenQueue { pending, tasks, .. } = &mut *queue;
+    let mut integrated = 0;
+    tasks.retain_mut(|task| {
+        if integrated >= settings.max_per_frame {
+            return true;
+        }
+        let Some(generated) = block_on(poll_once(task)) else {
+            return true;
+        };
+
+        // A chunk already loaded at these coordinates wins over a stale result.
+        let chunk = &generated.chunk;
+        let coords = [chunk.chunk_x, chunk.chunk_y, chunk.chunk_z];
+        if terrain.chunk(coords).is_some() {
+            return false;
+        }
+
+        // Edits since the task was spawned may not have reached it, so generate it again.
+        if generated.edits != terrain.edits {
+            pending.push_front(coords);
+            return false;
+        }
+
+        terrain.insert_generated(generated, &registry);
+        integrated += 1;
+        false
+    });
+}
+
+fn create_chunk_material(mut commands: Commands, mut materials: ResMut<Assets<StandardMaterial>>) {
+    commands.insert_resource(ChunkMaterial {
+        opaque: materials.add(Chunk::material()),
+        transparent: materials.add(Chunk::transparent_material()),
+    });
+}
+
+fn toggle_flat_lighting(keys: Res<Input<KeyCode>>, mut meshing: ResMut<MeshingSettings>) {
+    if !keys.just_pressed(KeyCode::F7) {
+        return;
+    }
+
+    meshing.flat_lighting = !meshing.flat_lighting;
+    info!("Flat lighting: {}", meshing.flat_lighting);
+}
+
+/// Makes the chunk materials unlit in flat lighting mode, and remeshes every chunk to add or
+/// drop its AO and light colors.
+fn apply_flat_lighting(
+    meshing: Res<MeshingSettings>,
+    material: Res<ChunkMaterial>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut terrain: ResMut<Terrain>,
+) {
+    let unlit = meshing.flat_lighting;
+    if materials
+        .get(&material.opaque)
+        .is_none_or(|opaque| opaque.unlit == unlit)
+    {
+        return;
+    }
+
+    for handle in [&material.opaque, &material.transparent] {
+        if let Some(standard) = materials.get_mut(handle) {
+            standard.unlit = unlit;
+        }
+    }
+    for chunk in &mut terrain.chunks {
+        chunk.dirty = true;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn process_terrain(
+    mut commands: Commands,
+    meshing: Res<MeshingSettings>,
+    registry: Res<BlockRegistry>,
+    mut terrain: ResMut<Terrain>,
+    mut mesh_tasks: ResMut<ChunkMeshTasks>,
+    render_settings: Res<RenderSettings>,
     q_player: Query<&Transform, With<Player>>,
 ) {
     let t_player = q_player.single();
+    let [player_x, player_y, player_z] = Chunk::coords_at(t_player.translation);
+    let pool = AsyncComputeTaskPool::get();
+    let mut remesh = Vec::new();
     for ref mut chunk in &mut terrain.chunks {
+        let coords = [chunk.chunk_x, chunk.chunk_y, chunk.chunk_z];
         let chunk_pos = Vec3::new(
             chunk.chunk_x as f32,
             chunk.chunk_y as f32,
@@ -51,32 +1090,1175 @@ fn process_terrain(
         let delta = (t_player.translation - chunk_pos).abs();
         let dist = (delta.x.powi(2) + delta.y.powi(2) + delta.z.powi(2)).sqrt();
 
-        let rd = RENDER_DISTANCE_CHUNKS as f32 * CHUNK_SIZE as f32 * VOXEL_SIZE as f32;
-
-        if chunk.entity.is_none() && dist < rd {
-            let transform = Vec3::new(
-                chunk.chunk_x as f32,
-                chunk.chunk_y as f32,
-                chunk.chunk_z as f32,
-            ) * CHUNK_SIZE as f32;
-            chunk.entity = Some(
-                commands
-                    .spawn((
+        let rd = render_settings.render_distance_chunks as f32 * CHUNK_SIZE as f32 * VOXEL_SIZE;
+
+        // The chunk the player is in and its neighbors always stay loaded.
+        let keep_alive = (chunk.chunk_x - player_x).abs() <= 1
+            && (chunk.chunk_y - player_y).abs() <= 1
+            && (chunk.chunk_z - player_z).abs() <= 1;
+
+        let needs_mesh =
+            chunk.dirty || (chunk.entity.is_none() && !mesh_tasks.0.contains_key(&coords));
+        if needs_mesh && (dist < rd || keep_alive) {
+            chunk.dirty = false;
+            remesh.push(coords);
+        } else if dist > rd && !keep_alive {
+            mesh_tasks.0.remove(&coords);
+            if let Some(entity) = chunk.entity.take() {
+                if let Some(e_cmds) = commands.get_entity(entity) {
+                    e_cmds.despawn_recursive();
+                }
+            }
+        }
+    }
+
+    for coords in remesh {
+        let Some(chunk) = terrain.chunk(coords) else {
+            continue;
+        };
+
+        // The padded chunk holds the neighbor voxels and light the mesher needs, so an owned copy
+        // is all the task takes. Replacing an in-flight task cancels the stale one.
+        let snapshot = chunk.clone();
+        let meshing = *meshing;
+        let registry = registry.clone();
+        let task = pool.spawn(async move {
+            let light = snapshot.light(&meshing);
+            (
+                snapshot.to_mesh(&meshing, MeshPass::Opaque, &registry, &light),
+                snapshot.to_mesh(&meshing, MeshPass::Transparent, &registry, &light),
+            )
+        });
+        mesh_tasks.0.insert(coords, task);
+    }
+}
+
+/// Spawns entities for finished mesh tasks, or swaps the new meshes onto a chunk's existing
+/// entity so it is never left without one.
+#[allow(clippy::too_many_arguments)]
+fn spawn_chunk_meshes(
+    mut commands: Commands,
+    entities: &Entities,
+    mut meshes: ResMut<Assets<Mesh>>,
+    material: Res<ChunkMaterial>,
+    spawn_settings: Res<ChunkSpawnSettings>,
+    mut terrain: ResMut<Terrain>,
+    mut mesh_tasks: ResMut<ChunkMeshTasks>,
+    q_children: Query<&Children>,
+) {
+    let start = Instant::now();
+    let mut queued = 0;
+    let mut spawn_batch = Vec::new();
+    let mut transparent_batch = Vec::new();
+    let mut links = Vec::new();
+    mesh_tasks.0.retain(|&coords, task| {
+        if queued >= spawn_settings.max_per_frame {
+            return true;
+        }
+        let Some((opaque_mesh, transparent_mesh)) = block_on(poll_once(task)) else {
+            return true;
+        };
+        let Some(chunk) = terrain.chunk_mut(coords) else {
+            return false;
+        };
+        queued += 1;
+
+        let mut transparent_mesh =
+            (transparent_mesh.count_vertices() > 0).then(|| meshes.add(transparent_mesh));
+        let existing = chunk
+            .entity
+            .filter(|&entity| commands.get_entity(entity).is_some());
+        let entity = match existing {
+            Some(entity) => {
+                // Each insert replaces the old handle, so the chunk keeps a mesh throughout.
+                commands.entity(entity).insert(meshes.add(opaque_mesh));
+                let child = q_children
+                    .get(entity)
+                    .ok()
+                    .and_then(|children| children.first().copied());
+                if let Some(child) = child {
+                    match transparent_mesh.take() {
+                        Some(mesh) => {
+                            commands.entity(child).insert(mesh);
+                        }
+                        None => commands.entity(child).despawn_recursive(),
+                    }
+                }
+                entity
+            }
+            None => {
+                let transform = Vec3::new(
+                    chunk.chunk_x as f32,
+                    chunk.chunk_y as f32,
+                    chunk.chunk_z as f32,
+                ) * CHUNK_SIZE as f32;
+                let entity = entities.reserve_entity();
+                chunk.entity = Some(entity);
+                spawn_batch.push((
+                    entity,
+                    (
                         PbrBundle {
-                            mesh: meshes.add(chunk.to_mesh()),
-                            material: materials.add(chunk.to_material()),
+                            mesh: meshes.add(opaque_mesh),
+                            material: material.opaque.clone(),
                             transform: Transform::from_translation(transform),
                             ..default()
                         },
                         Wireframe,
-                    ))
-                    .id(),
-            );
-        } else if chunk.entity.is_some() && dist > rd {
-            if let Some(mut e_cmds) = commands.get_entity(chunk.entity.unwrap()) {
-                chunk.entity = None;
-                e_cmds.despawn();
+                    ),
+                ));
+                entity
+            }
+        };
+
+        // Transparent voxels go on a child entity so they're drawn with the blended material.
+        if let Some(mesh) = transparent_mesh {
+            let child = entities.reserve_entity();
+            links.push((entity, child));
+            transparent_batch.push((
+                child,
+                PbrBundle {
+                    mesh,
+                    material: material.transparent.clone(),
+                    ..default()
+                },
+            ));
+        }
+        false
+    });
+
+    if queued == 0 {
+        return;
+    }
+    commands.insert_or_spawn_batch(spawn_batch);
+    if !transparent_batch.is_empty() {
+        commands.insert_or_spawn_batch(transparent_batch);
+        // `Parent` can only be set through the hierarchy API, so link every pair in one command.
+        commands.add(move |world: &mut World| {
+            for (parent, child) in links {
+                world.entity_mut(parent).push_children(&[child]);
             }
+        });
+    }
+    debug!(
+        "Queued {queued} chunk meshes in {:?}, {} tasks left",
+        start.elapsed(),
+        mesh_tasks.0.len()
+    );
+}
+
+fn export_heightmap(
+    keys: Res<Input<KeyCode>>,
+    params: Res<TerrainParams>,
+    export: Res<HeightmapExport>,
+) {
+    if !keys.just_pressed(KeyCode::F9) {
+        return;
+    }
+
+    let noise = TerrainNoise::new(WORLD_SEED, &params);
+    match export.to_image(&noise).save(&export.path) {
+        Ok(()) => info!("Saved heightmap to {}", export.path),
+        Err(err) => error!("Failed to save heightmap to {}: {err}", export.path),
+    }
+}
+
+/// Marks the border with four translucent walls, matching the clamp on X and Z.
+fn spawn_world_border(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    border: Res<WorldBorder>,
+) {
+    let extent = border.extent();
+    let mesh =
+        meshes.add(shape::Quad::new(Vec2::new(extent * 2.0, WORLD_BORDER_WALL_HEIGHT)).into());
+    let material = materials.add(StandardMaterial {
+        base_color: Color::rgba(0.3, 0.6, 1.0, 0.1),
+        alpha_mode: AlphaMode::Blend,
+        cull_mode: None,
+        unlit: true,
+        ..default()
+    });
+
+    for (translation, yaw) in [
+        (Vec3::new(0.0, 0.0, -extent), 0.0),
+        (Vec3::new(0.0, 0.0, extent), PI),
+        (Vec3::new(-extent, 0.0, 0.0), FRAC_PI_2),
+        (Vec3::new(extent, 0.0, 0.0), -FRAC_PI_2),
+    ] {
+        commands.spawn((
+            PbrBundle {
+                mesh: mesh.clone(),
+                material: material.clone(),
+                transform: Transform::from_translation(translation)
+                    .with_rotation(Quat::from_rotation_y(yaw)),
+                ..default()
+            },
+            BorderWall,
+        ));
+    }
+}
+
+fn clamp_to_world_border(
+    border: Res<WorldBorder>,
+    mut q_player: Query<&mut Transform, With<Player>>,
+) {
+    let mut t_player = q_player.single_mut();
+    let extent = border.extent();
+    t_player.translation.x = t_player.translation.x.clamp(-extent, extent);
+    t_player.translation.z = t_player.translation.z.clamp(-extent, extent);
+}
+
+fn check_duplicate_chunks(debug: Res<TerrainDebug>, terrain: Res<Terrain>) {
+    if !debug.check_duplicates {
+        return;
+    }
+
+    for coords in terrain.duplicate_chunks() {
+        error!("Duplicate chunk at {coords:?}");
+    }
+}
+
+fn toggle_memory_measurement(keys: Res<Input<KeyCode>>, mut terrain_debug: ResMut<TerrainDebug>) {
+    if !keys.just_pressed(KeyCode::F8) {
+        return;
+    }
+
+    terrain_debug.measure_memory = !terrain_debug.measure_memory;
+    info!("Chunk memory measurement: {}", terrain_debug.measure_memory);
+}
+
+fn measure_chunk_memory(
+    debug: Res<TerrainDebug>,
+    terrain: Res<Terrain>,
+    mut diagnostics: Diagnostics,
+) {
+    if !debug.measure_memory {
+        return;
+    }
+
+    diagnostics.add_measurement(CHUNK_MEMORY, || {
+        terrain
+            .chunks
+            .iter()
+            .map(Chunk::memory_bytes)
+            .sum::<usize>() as f64
+            / (1024.0 * 1024.0)
+    });
+}
+
+fn log_chunk_histogram(
+    keys: Res<Input<KeyCode>>,
+    terrain: Res<Terrain>,
+    registry: Res<BlockRegistry>,
+    q_player: Query<&Transform, With<Player>>,
+) {
+    if !keys.just_pressed(KeyCode::F4) {
+        return;
+    }
+
+    let coords = Chunk::coords_at(q_player.single().translation);
+    let Some(chunk) = terrain.chunk(coords) else {
+        info!("No chunk loaded at {coords:?}");
+        return;
+    };
+
+    let total = CHUNK_SIZE.pow(3) as f32;
+    let mut counts = chunk.histogram().into_iter().collect::<Vec<_>>();
+    counts.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+    let summary = counts
+        .iter()
+        .map(|(voxel, count)| {
+            let name = match voxel {
+                Some(voxel) => registry
+                    .get(voxel.block)
+                    .map_or_else(|| format!("{:?}", voxel.block), |def| def.name.clone()),
+                None => "Air".to_string(),
+            };
+            format!("{name} {:.1}%", *count as f32 / total * 100.0)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    info!("Chunk {coords:?}: {summary}");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, thread, time::Duration};
+
+    use bevy::{diagnostic::DiagnosticsStore, ecs::system::RunSystemOnce, tasks::block_on};
+
+    use super::*;
+    use crate::{
+        block::BlockId,
+        chunk::Skylight,
+        test_utils::{registry, sky_terrain, GLASS, GLOWSTONE, SKY, SKY_CHUNK, STONE},
+        voxel::LIQUID_LEVEL_FULL,
+    };
+
+    /// A world with `chunks` loaded, the player at the origin, and everything else the meshing
+    /// systems need.
+    fn meshing_world(chunks: Vec<Chunk>) -> World {
+        AsyncComputeTaskPool::get_or_init(TaskPool::new);
+        let mut terrain = Terrain::default();
+        for chunk in chunks {
+            terrain.insert(chunk);
+        }
+
+        let mut world = World::new();
+        world.insert_resource(terrain);
+        world.init_resource::<MeshingSettings>();
+        world.init_resource::<ChunkMeshTasks>();
+        world.insert_resource(registry());
+        world.init_resource::<ChunkSpawnSettings>();
+        // Far enough to reach the sky chunks from the origin.
+        world.insert_resource(RenderSettings {
+            render_distance_chunks: 16,
+            ..default()
+        });
+        world.init_resource::<Assets<Mesh>>();
+        let mut materials = Assets::<StandardMaterial>::default();
+        world.insert_resource(ChunkMaterial {
+            opaque: materials.add(Chunk::material()),
+            transparent: materials.add(Chunk::transparent_material()),
+        });
+        world.insert_resource(materials);
+        world.spawn((Transform::default(), Player));
+        world
+    }
+
+    /// Queues mesh tasks for every chunk that needs one and spawns the results as they finish.
+    fn mesh_all(world: &mut World) {
+        world.run_system_once(process_terrain);
+        for _ in 0..6000 {
+            world.run_system_once(spawn_chunk_meshes);
+            if world.resource::<ChunkMeshTasks>().0.is_empty() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("mesh tasks didn't finish");
+    }
+
+    #[test]
+    fn generated_chunks_wait_for_their_frame_and_a_fresh_neighborhood() {
+        let registry = registry();
+        let noise = Arc::new(TerrainNoise::new(WORLD_SEED, &TerrainParams::default()));
+        let pool = GenWorkerPool::new(2);
+        let mut terrain = Terrain::new(noise.clone());
+        let [a, b, c, d] = [[0, 0, 0], [1, 0, 0], [2, 0, 0], [3, 0, 0]];
+        let spawn = |terrain: &Terrain, coords| {
+            let neighborhood = terrain.neighborhood(coords);
+            let noise = noise.clone();
+            pool.0.spawn(async move { neighborhood.generate(&noise) })
+        };
+        let mut tasks = vec![spawn(&terrain, a), spawn(&terrain, b)];
+        terrain.set_voxel([0, SKY, 0], Some(STONE), &registry);
+        tasks.extend([spawn(&terrain, c), spawn(&terrain, d)]);
+        while !tasks.iter().all(Task::is_finished) {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut world = World::new();
+        world.insert_resource(registry);
+        world.insert_resource(terrain);
+        world.insert_resource(ChunkGenSettings { max_per_frame: 1 });
+        world.insert_resource(ChunkGenQueue {
+            noise: noise.clone(),
+            pending: VecDeque::new(),
+            tasks,
+        });
+
+        // The edit made A and B stale, so they go back in the queue; D waits for the next frame.
+        world.run_system_once(poll_gen_tasks);
+        let queue = world.resource::<ChunkGenQueue>();
+        assert_eq!(queue.pending, [b, a]);
+        assert_eq!(queue.tasks.len(), 1);
+        let terrain = world.resource::<Terrain>();
+        assert_eq!(terrain.chunks.len(), 1);
+        assert!(terrain.chunk(c).is_some());
+
+        world.run_system_once(poll_gen_tasks);
+        assert!(world.resource::<ChunkGenQueue>().tasks.is_empty());
+        assert!(world.resource::<Terrain>().chunk(d).is_some());
+    }
+
+    #[test]
+    fn generation_on_the_dedicated_pool_completes() {
+        let pool = GenWorkerPool::new(2);
+        let thread_name = block_on(
+            pool.0
+                .spawn(async { thread::current().name().map(String::from) }),
+        );
+        assert!(thread_name.is_some_and(|name| name.starts_with("Chunk Generation")));
+
+        let noise = Arc::new(TerrainNoise::new(WORLD_SEED, &TerrainParams::default()));
+        let mut world = World::new();
+        world.insert_resource(pool);
+        world.insert_resource(registry());
+        world.insert_resource(Terrain::default());
+        world.init_resource::<ChunkGenSettings>();
+        world.insert_resource(ChunkGenQueue {
+            noise: noise.clone(),
+            pending: VecDeque::from([[0, 0, 0], [2, -1, 0]]),
+            tasks: Vec::new(),
+        });
+
+        world.run_system_once(spawn_gen_tasks);
+        for _ in 0..1000 {
+            world.run_system_once(poll_gen_tasks);
+            if world.resource::<ChunkGenQueue>().tasks.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let terrain = world.resource::<Terrain>();
+        assert_eq!(terrain.chunks.len(), 2);
+        for coords in [[0, 0, 0], [2, -1, 0]] {
+            let [x, y, z] = coords;
+            let chunk = terrain.chunk(coords).unwrap();
+            assert_eq!(chunk.histogram(), Chunk::new(&noise, x, y, z).histogram());
+        }
+    }
+
+    #[test]
+    fn chunks_keep_a_mesh_through_a_remesh() {
+        let registry = registry();
+        let mut terrain = sky_terrain(&[[0, 0, 0], [1, 0, 0]]);
+        terrain.set_voxel([0, SKY, 0], Some(STONE), &registry);
+        terrain.set_voxel([1, SKY, 0], Some(GLASS), &registry);
+        terrain.set_voxel([32, SKY, 0], Some(STONE), &registry);
+        let mut world = meshing_world(terrain.chunks);
+        mesh_all(&mut world);
+
+        let meshes = |world: &mut World| {
+            let entity = world
+                .resource::<Terrain>()
+                .chunk([0, SKY_CHUNK, 0])?
+                .entity?;
+            let child = *world.get::<Children>(entity)?.first()?;
+            let opaque = world.get::<Handle<Mesh>>(entity)?.clone();
+            let transparent = world.get::<Handle<Mesh>>(child)?.clone();
+            Some((entity, child, opaque, transparent))
+        };
+        let (entity, child, opaque, transparent) = meshes(&mut world).unwrap();
+
+        let assert_all_meshed = |world: &mut World| {
+            let entities = world
+                .resource::<Terrain>()
+                .chunks
+                .iter()
+                .map(|chunk| chunk.entity.expect("chunk lost its mesh entity"))
+                .collect::<Vec<_>>();
+            for entity in entities {
+                assert!(world.get::<Handle<Mesh>>(entity).is_some());
+            }
+        };
+
+        world
+            .resource_mut::<Terrain>()
+            .set_voxel([16, SKY, 0], Some(STONE), &registry);
+        world.run_system_once(process_terrain);
+        assert!(!world.resource::<ChunkMeshTasks>().0.is_empty());
+        assert_all_meshed(&mut world);
+
+        for _ in 0..6000 {
+            world.run_system_once(spawn_chunk_meshes);
+            assert_all_meshed(&mut world);
+            if world.resource::<ChunkMeshTasks>().0.is_empty() {
+                // The new meshes were swapped onto the same entities.
+                let chunk = world
+                    .resource::<Terrain>()
+                    .chunk([0, SKY_CHUNK, 0])
+                    .unwrap();
+                assert_eq!(chunk.entity, Some(entity));
+                let remeshed = meshes(&mut world).unwrap();
+                assert_eq!(remeshed.1, child);
+                assert_ne!(remeshed.2, opaque);
+                assert_ne!(remeshed.3, transparent);
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("mesh tasks didn't finish");
+    }
+
+    #[test]
+    fn heightmap_brightness_follows_height() {
+        let export = HeightmapExport {
+            min: IVec2::new(-20, 5),
+            size: UVec2::new(48, 32),
+            ..default()
+        };
+        let noise = TerrainNoise::new(WORLD_SEED, &TerrainParams::default());
+        let image = export.to_image(&noise);
+        assert_eq!(image.dimensions(), (48, 32));
+
+        let heights = surface_heights(&noise, -20, 5, 48, 32);
+        let pixel = |i: usize| image.get_pixel(i as u32 % 48, i as u32 / 48)[0];
+        let (highest, _) = heights.iter().enumerate().max_by_key(|(_, h)| **h).unwrap();
+        let (lowest, _) = heights.iter().enumerate().min_by_key(|(_, h)| **h).unwrap();
+        assert!(heights[highest] > heights[lowest]);
+        assert!(pixel(highest) > pixel(lowest));
+    }
+
+    #[test]
+    fn nothing_past_the_border_is_queued() {
+        let mut world = World::new();
+        world.insert_resource(TerrainParams::default());
+        world.insert_resource(WorldBorder { radius_chunks: 4 });
+        world.insert_resource(PlayerSpawn::default());
+        world.init_resource::<Terrain>();
+        world.run_system_once(queue_chunks);
+
+        let pending = &world.resource::<ChunkGenQueue>().pending;
+        assert_eq!(pending.len(), 9usize.pow(3));
+        assert!(pending
+            .iter()
+            .all(|coords| coords.iter().all(|c| c.abs() <= 4)));
+        assert!(!pending.contains(&[5, 0, 0]));
+        assert!(!pending.contains(&[0, -5, 0]));
+    }
+
+    #[test]
+    fn border_walls_line_up_with_the_clamp() {
+        let mut world = World::new();
+        let border = WorldBorder { radius_chunks: 1 };
+        world.insert_resource(border);
+        world.insert_resource(Assets::<Mesh>::default());
+        world.insert_resource(Assets::<StandardMaterial>::default());
+        let player = world
+            .spawn((Player, Transform::from_xyz(500.0, -500.0, 3.0)))
+            .id();
+
+        world.run_system_once(spawn_world_border);
+        world.run_system_once(clamp_to_world_border);
+        let extent = border.extent();
+        assert_eq!(
+            world.get::<Transform>(player).unwrap().translation,
+            Vec3::new(extent, -500.0, 3.0)
+        );
+
+        let mut q_walls = world.query_filtered::<&Transform, With<BorderWall>>();
+        let mut walls = q_walls
+            .iter(&world)
+            .map(|transform| {
+                // Quads face +Z, so each wall's normal should point along X or Z.
+                let normal = (transform.rotation * Vec3::Z).abs().round();
+                (transform.translation.to_array(), normal.to_array())
+            })
+            .collect::<Vec<_>>();
+        walls.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(
+            walls,
+            vec![
+                ([-extent, 0.0, 0.0], [1.0, 0.0, 0.0]),
+                ([0.0, 0.0, -extent], [0.0, 0.0, 1.0]),
+                ([0.0, 0.0, extent], [0.0, 0.0, 1.0]),
+                ([extent, 0.0, 0.0], [1.0, 0.0, 0.0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_players_chunk_and_neighbors_stay_loaded() {
+        let registry = registry();
+        let terrain = sky_terrain(&[[0, 0, 0], [1, 0, 0], [9, 0, 0]]);
+        let mut world = meshing_world(terrain.chunks);
+        world
+            .resource_mut::<RenderSettings>()
+            .render_distance_chunks = 0;
+        let mut q_player = world.query_filtered::<&mut Transform, With<Player>>();
+        q_player.single_mut(&mut world).translation = Vec3::new(5.0, SKY as f32 + 5.0, 5.0);
+        let entities = (0..3).map(|_| world.spawn_empty().id()).collect::<Vec<_>>();
+        for (chunk, &entity) in world
+            .resource_mut::<Terrain>()
+            .chunks
+            .iter_mut()
+            .zip(&entities)
+        {
+            chunk.entity = Some(entity);
+        }
+
+        // An edit next door still gets meshed, even though it's past the render distance.
+        world
+            .resource_mut::<Terrain>()
+            .set_voxel([32, SKY, 0], Some(STONE), &registry);
+        world.run_system_once(process_terrain);
+
+        let tasks = &world.resource::<ChunkMeshTasks>().0;
+        assert!(tasks.contains_key(&[1, SKY_CHUNK, 0]));
+        assert!(!tasks.contains_key(&[9, SKY_CHUNK, 0]));
+        let terrain = world.resource::<Terrain>();
+        assert!(!terrain.chunk([1, SKY_CHUNK, 0]).unwrap().dirty);
+        assert_eq!(
+            terrain.chunk([0, SKY_CHUNK, 0]).unwrap().entity,
+            Some(entities[0])
+        );
+        assert_eq!(
+            terrain.chunk([1, SKY_CHUNK, 0]).unwrap().entity,
+            Some(entities[1])
+        );
+        assert_eq!(terrain.chunk([9, SKY_CHUNK, 0]).unwrap().entity, None);
+        assert!(world.get_entity(entities[0]).is_some());
+        assert!(world.get_entity(entities[1]).is_some());
+        assert!(world.get_entity(entities[2]).is_none());
+    }
+
+    #[test]
+    fn flat_lighting_draws_chunks_unlit() {
+        let noise = TerrainNoise::new(WORLD_SEED, &TerrainParams::default());
+        let chunks = vec![Chunk::new(&noise, 0, 0, 0), Chunk::new(&noise, 1, 0, 0)];
+        let mut world = meshing_world(chunks);
+        mesh_all(&mut world);
+
+        world.resource_mut::<MeshingSettings>().flat_lighting = true;
+        world.run_system_once(apply_flat_lighting);
+        mesh_all(&mut world);
+
+        let mut q_chunks = world.query::<(&Handle<Mesh>, &Handle<StandardMaterial>)>();
+        let handles = q_chunks
+            .iter(&world)
+            .map(|(mesh, material)| (mesh.clone(), material.clone()))
+            .collect::<Vec<_>>();
+        // Both chunks, plus transparent children for any water.
+        assert!(handles.len() >= 2);
+        let meshes = world.resource::<Assets<Mesh>>();
+        let materials = world.resource::<Assets<StandardMaterial>>();
+        for (mesh, material) in handles {
+            assert!(materials.get(&material).unwrap().unlit);
+            assert!(meshes
+                .get(&mesh)
+                .unwrap()
+                .attribute(Mesh::ATTRIBUTE_COLOR)
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn load_order_does_not_change_chunks() {
+        let noise = TerrainNoise::new(WORLD_SEED, &TerrainParams::default());
+        // Two neighbors whose shared border crosses the surface.
+        let surface = surface_heights(&noise, 15, 0, 1, 1)[0];
+        let a = Chunk::coords_at(Vec3::new(15.0, surface as f32, 0.0));
+        let b = [a[0] + 1, a[1], a[2]];
+
+        let meshes_loading = |order: [[isize; 3]; 2]| {
+            let mut world = meshing_world(Vec::new());
+            let mut q_player = world.query_filtered::<&mut Transform, With<Player>>();
+            q_player.single_mut(&mut world).translation = Vec3::new(16.0, surface as f32, 0.0);
+            for [x, y, z] in order {
+                let chunk = Chunk::new(&noise, x, y, z);
+                world.resource_mut::<Terrain>().insert(chunk);
+                mesh_all(&mut world);
+            }
+
+            let entities = world
+                .resource::<Terrain>()
+                .chunks
+                .iter()
+                .map(|chunk| ([chunk.chunk_x, chunk.chunk_y, chunk.chunk_z], chunk.entity))
+                .collect::<Vec<_>>();
+            let mut meshes = entities
+                .into_iter()
+                .map(|(coords, entity)| {
+                    let handle = world.get::<Handle<Mesh>>(entity.unwrap()).unwrap();
+                    let mesh = world.resource::<Assets<Mesh>>().get(handle).unwrap();
+                    let positions = mesh.attribute(Mesh::ATTRIBUTE_POSITION).unwrap();
+                    (coords, positions.get_bytes().to_vec())
+                })
+                .collect::<Vec<_>>();
+            meshes.sort();
+            meshes
+        };
+
+        let a_first = meshes_loading([a, b]);
+        let b_first = meshes_loading([b, a]);
+        assert!(a_first.iter().all(|(_, positions)| !positions.is_empty()));
+        assert!(a_first == b_first, "chunk meshes depend on load order");
+    }
+
+    #[test]
+    fn duplicate_chunks_are_reported() {
+        let mut terrain = sky_terrain(&[[0, 0, 0], [1, 0, 0]]);
+        assert!(terrain.duplicate_chunks().is_empty());
+
+        let copy = terrain.chunks[1].clone();
+        terrain.chunks.push(copy);
+        assert_eq!(terrain.duplicate_chunks(), vec![[1, SKY_CHUNK, 0]]);
+    }
+
+    #[test]
+    fn f8_turns_memory_measurement_on() {
+        let mut world = World::new();
+        let mut keys = Input::<KeyCode>::default();
+        keys.press(KeyCode::F8);
+        world.insert_resource(keys);
+        world.init_resource::<TerrainDebug>();
+        let noise = TerrainNoise::new(WORLD_SEED, &TerrainParams::default());
+        let mut terrain = Terrain::default();
+        terrain.insert(Chunk::new(&noise, 0, 0, 0));
+        world.insert_resource(terrain);
+        let mut store = DiagnosticsStore::default();
+        store.add(Diagnostic::new(CHUNK_MEMORY, "chunk_memory_mib", 20));
+        world.insert_resource(store);
+        let measured = |world: &World| {
+            world
+                .resource::<DiagnosticsStore>()
+                .get(CHUNK_MEMORY)
+                .and_then(Diagnostic::value)
+        };
+
+        world.run_system_once(measure_chunk_memory);
+        assert_eq!(measured(&world), None);
+
+        world.run_system_once(toggle_memory_measurement);
+        world.run_system_once(measure_chunk_memory);
+        assert!(measured(&world).is_some_and(|mib| mib > 0.0));
+    }
+
+    #[test]
+    fn batched_spawns_give_one_entity_per_chunk() {
+        let noise = TerrainNoise::new(
+            WORLD_SEED,
+            &TerrainParams {
+                water_level: None,
+                ..default()
+            },
+        );
+        let mut chunks = [[0, 0, 0], [1, 0, 0], [0, 0, -1], [-2, 0, 1]]
+            .map(|[x, y, z]| Chunk::new(&noise, x, y, z))
+            .to_vec();
+        Arc::make_mut(&mut chunks[1].storage).set([0, 0, 0], Some(GLASS));
+        let mut world = meshing_world(chunks);
+        mesh_all(&mut world);
+
+        let chunks = world.resource::<Terrain>().chunks.clone();
+        let mut q_meshes = world.query_filtered::<(Entity, &Transform), With<Wireframe>>();
+        let spawned = q_meshes
+            .iter(&world)
+            .map(|(entity, transform)| (entity, transform.translation))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(spawned.len(), chunks.len());
+        for chunk in &chunks {
+            let coords = [chunk.chunk_x, chunk.chunk_y, chunk.chunk_z];
+            let translation = Vec3::from_array(coords.map(|c| (c * CHUNK_SIZE as isize) as f32));
+            assert_eq!(spawned[&chunk.entity.unwrap()], translation);
+        }
+
+        // Only the chunk holding the glass gets a transparent child.
+        let mut q_children = world.query_filtered::<&Parent, Without<Wireframe>>();
+        let parents = q_children
+            .iter(&world)
+            .map(|parent| Some(parent.get()))
+            .collect::<Vec<_>>();
+        assert_eq!(parents, vec![chunks[1].entity]);
+    }
+
+    #[test]
+    fn spawns_are_capped_per_frame() {
+        let terrain = sky_terrain(&[[0, 0, 0], [1, 0, 0], [2, 0, 0], [3, 0, 0], [4, 0, 0]]);
+        let mut world = meshing_world(terrain.chunks);
+        world.resource_mut::<ChunkSpawnSettings>().max_per_frame = 2;
+        world.run_system_once(process_terrain);
+        while !world
+            .resource::<ChunkMeshTasks>()
+            .0
+            .values()
+            .all(Task::is_finished)
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        world.run_system_once(spawn_chunk_meshes);
+        let mut q_meshes = world.query_filtered::<Entity, With<Wireframe>>();
+        assert_eq!(q_meshes.iter(&world).count(), 2);
+        assert_eq!(world.resource::<ChunkMeshTasks>().0.len(), 3);
+        mesh_all(&mut world);
+        assert_eq!(q_meshes.iter(&world).count(), 5);
+    }
+
+    #[test]
+    fn a_hundred_mesh_requests_all_finish() {
+        let noise = TerrainNoise::new(WORLD_SEED, &TerrainParams::default());
+        let mut chunks = Vec::new();
+        for x in -2..3 {
+            for y in -2..2 {
+                for z in -2..3 {
+                    chunks.push(Chunk::new(&noise, x, y, z));
+                }
+            }
+        }
+        assert_eq!(chunks.len(), 100);
+
+        let mut world = meshing_world(chunks);
+        mesh_all(&mut world);
+
+        let chunks = world.resource::<Terrain>().chunks.clone();
+        for chunk in chunks {
+            let entity = chunk.entity.expect("chunk wasn't meshed");
+            assert!(world.get::<Handle<Mesh>>(entity).is_some());
+        }
+    }
+
+    #[test]
+    fn highest_solid_skips_water() {
+        let registry = registry();
+        let mut terrain = sky_terrain(&[[0, 0, 0]]);
+        let water = Voxel {
+            block: BlockId::WATER,
+            level: LIQUID_LEVEL_FULL,
+        };
+        terrain.set_voxel([0, SKY, 0], Some(STONE), &registry);
+        for y in 1..4 {
+            terrain.set_voxel([0, SKY + y, 0], Some(water), &registry);
+        }
+
+        assert_eq!(terrain.highest_solid(0, 0, &registry), Some(SKY));
+        assert_eq!(terrain.highest_voxel(0, 0), Some(SKY + 3));
+        assert_eq!(terrain.highest_solid(1, 0, &registry), None);
+    }
+
+    #[test]
+    fn explosions_remove_solid_voxels_within_the_radius() {
+        let registry = registry();
+        let mut terrain = sky_terrain(&[[0, 0, 0], [1, 0, 0]]);
+        let water = Voxel {
+            block: BlockId::WATER,
+            level: LIQUID_LEVEL_FULL,
+        };
+        for x in 10..23 {
+            for y in SKY - 6..SKY + 7 {
+                for z in -6..7 {
+                    terrain.set_voxel([x, y, z], Some(STONE), &registry);
+                }
+            }
+        }
+        terrain.set_voxel([16, SKY + 1, 0], Some(water), &registry);
+        for chunk in &mut terrain.chunks {
+            chunk.dirty = false;
+        }
+
+        // Centered on the border between the two chunks, the blast reaches into both.
+        let center = Vec3::new(16.0, SKY as f32, 0.0) * VOXEL_SIZE;
+        let removed = terrain.explode(center, 3.0 * VOXEL_SIZE, &registry);
+
+        assert_eq!(removed, 122);
+        assert_eq!(terrain.voxel([16, SKY, 0]), None);
+        assert_eq!(terrain.voxel([15, SKY + 2, -2]), None);
+        assert_eq!(terrain.voxel([19, SKY, 0]), None);
+        assert_eq!(terrain.voxel([16, SKY + 1, 0]), Some(water));
+        assert_eq!(terrain.voxel([20, SKY, 0]), Some(STONE));
+        assert_eq!(terrain.voxel([18, SKY + 2, 2]), Some(STONE));
+        assert!(terrain.chunks.iter().all(|chunk| chunk.dirty));
+    }
+
+    #[test]
+    fn edits_reach_the_padding_next_door() {
+        let registry = registry();
+        let mut terrain = sky_terrain(&[[0, 0, 0], [1, 0, 0], [3, 0, 0]]);
+        terrain.set_voxel([16, SKY, 0], Some(STONE), &registry);
+
+        let [chunk, next, far] = [0, 1, 2].map(|i| &terrain.chunks[i]);
+        assert_eq!(chunk.storage.get(&[16, 0, 0]), Some(&Voxel::default()));
+        assert_eq!(next.storage.get(&[-16, 0, 0]), Some(&Voxel::default()));
+        assert!(chunk.dirty && next.dirty && !far.dirty);
+        assert_eq!(terrain.voxel([16, SKY, 0]), Some(STONE));
+    }
+
+    #[test]
+    fn raycast_crosses_chunk_borders() {
+        let registry = registry();
+        let mut terrain = sky_terrain(&[[0, 0, 0], [1, 0, 0]]);
+        terrain.set_voxel([17, SKY, 0], Some(STONE), &registry);
+
+        let origin = Vec3::new(13.0, SKY as f32, 0.0);
+        let hit = terrain.raycast(origin, Vec3::X, 5.0, &registry);
+        assert_eq!(hit, Some(([17, SKY, 0], Face::Left)));
+        assert_eq!(terrain.raycast(origin, Vec3::X, 3.0, &registry), None);
+    }
+
+    #[test]
+    fn raycast_hits_the_block_it_starts_in() {
+        let registry = registry();
+        let mut terrain = sky_terrain(&[[0, 0, 0]]);
+        terrain.set_voxel([0, SKY, 0], Some(STONE), &registry);
+        terrain.set_voxel([0, SKY, 1], Some(STONE), &registry);
+
+        let origin = Vec3::new(0.2, SKY as f32, 0.0);
+        let hit = terrain.raycast(origin, Vec3::new(0.0, 0.0, 1.0), 5.0, &registry);
+        assert_eq!(hit, Some(([0, SKY, 0], Face::Back)));
+    }
+
+    #[test]
+    fn raycast_targets_glass_through_water() {
+        let registry = registry();
+        let mut terrain = sky_terrain(&[[0, 0, 0]]);
+        let water = Voxel {
+            block: BlockId::WATER,
+            level: LIQUID_LEVEL_FULL,
+        };
+        terrain.set_voxel([0, SKY, 2], Some(water), &registry);
+        terrain.set_voxel([0, SKY, 3], Some(GLASS), &registry);
+
+        let origin = Vec3::new(0.0, SKY as f32, 0.0);
+        let hit = terrain.raycast(origin, Vec3::Z, 5.0, &registry);
+        assert_eq!(hit, Some(([0, SKY, 3], Face::Back)));
+    }
+
+    #[test]
+    fn lookups_find_only_the_neighbors() {
+        let registry = registry();
+        let mut terrain = Terrain::default();
+        for x in 0..16 {
+            for y in 0..8 {
+                for z in 0..16 {
+                    terrain.insert(Chunk {
+                        storage: Arc::default(),
+                        chunk_x: x,
+                        chunk_y: y,
+                        chunk_z: z,
+                        entity: None,
+                        column_tops: vec![isize::MIN; CHUNK_SIZE_PADDED.pow(2)],
+                        skylight: Skylight::Uniform(MAX_LIGHT),
+                        block_light: HashMap::new(),
+                        dirty: false,
+                    });
+                }
+            }
+        }
+        assert_eq!(terrain.chunks.len(), 2048);
+
+        for coords in [[0, 0, 0], [7, 3, 9], [15, 7, 15]] {
+            let chunk = terrain.chunk(coords).unwrap();
+            assert_eq!([chunk.chunk_x, chunk.chunk_y, chunk.chunk_z], coords);
+        }
+        assert!(terrain.chunk([16, 0, 0]).is_none());
+
+        // The corner voxel of chunk [7, 3, 9] is also held in the padding of the seven chunks it
+        // touches. Glass lets the light through, so no other chunk changes.
+        let corner = [7, 3, 9].map(|c| c * CHUNK_SIZE as isize + CHUNK_SIZE as isize / 2 - 1);
+        terrain.set_voxel(corner, Some(GLASS), &registry);
+        let mut dirty = terrain
+            .chunks
+            .iter()
+            .filter(|chunk| chunk.dirty)
+            .map(|chunk| [chunk.chunk_x, chunk.chunk_y, chunk.chunk_z])
+            .collect::<Vec<_>>();
+        dirty.sort();
+        let mut expected = Vec::new();
+        for x in 7..=8 {
+            for y in 3..=4 {
+                for z in 9..=10 {
+                    expected.push([x, y, z]);
+                }
+            }
+        }
+        assert_eq!(dirty, expected);
+    }
+
+    #[test]
+    fn generated_chunks_pick_up_edits_next_door() {
+        let registry = registry();
+        let mut terrain = sky_terrain(&[[0, 0, 0]]);
+        terrain.set_voxel([15, SKY, 0], Some(STONE), &registry);
+        terrain.set_voxel([14, SKY, 5], Some(GLOWSTONE), &registry);
+        terrain.chunks[0].dirty = false;
+
+        let params = TerrainParams {
+            water_level: None,
+            ..default()
+        };
+        let noise = TerrainNoise::new(WORLD_SEED, &params);
+        terrain.insert_generated(
+            terrain.neighborhood([1, SKY_CHUNK, 0]).generate(&noise),
+            &registry,
+        );
+
+        let next = terrain.chunk([1, SKY_CHUNK, 0]).unwrap();
+        assert_eq!(next.storage.get(&[-17, 0, 0]), Some(&STONE));
+        assert_eq!(next.column_tops[Chunk::column_index(-17, 0)], SKY);
+        assert_eq!(next.block_light[&[-16, 0, 5]], MAX_LIGHT - 2);
+        assert!(next.dirty);
+        assert!(terrain.chunk([0, SKY_CHUNK, 0]).unwrap().dirty);
+    }
+
+    #[test]
+    fn block_light_crosses_chunk_borders() {
+        let registry = registry();
+        let mut terrain = sky_terrain(&[[0, 0, 0], [1, 0, 0]]);
+        terrain.set_voxel([14, SKY, 0], Some(GLOWSTONE), &registry);
+
+        assert_eq!(terrain.block_light([14, SKY, 0]), MAX_LIGHT);
+        assert_eq!(terrain.block_light([16, SKY, 0]), MAX_LIGHT - 2);
+        assert_eq!(terrain.block_light([20, SKY, 0]), MAX_LIGHT - 6);
+        let padding = terrain.chunk([0, SKY_CHUNK, 0]).unwrap().block_light[&[16, 0, 0]];
+        assert_eq!(padding, MAX_LIGHT - 2);
+    }
+
+    #[test]
+    fn block_light_stops_at_stone_in_another_chunk() {
+        let registry = registry();
+        let mut terrain = sky_terrain(&[[0, 0, 0], [1, 0, 0]]);
+        for y in -16..16 {
+            for z in -16..16 {
+                terrain.set_voxel([16, SKY + y, z], Some(STONE), &registry);
+            }
+        }
+        terrain.set_voxel([14, SKY, 0], Some(GLOWSTONE), &registry);
+
+        assert_eq!(terrain.block_light([15, SKY, 0]), MAX_LIGHT - 1);
+        assert_eq!(terrain.block_light([16, SKY, 0]), 0);
+        assert_eq!(terrain.block_light([17, SKY, 0]), 0);
+    }
+
+    #[test]
+    fn breaking_a_light_darkens_everything_it_lit() {
+        let registry = registry();
+        let mut terrain = sky_terrain(&[[0, 0, 0], [1, 0, 0]]);
+        terrain.set_voxel([14, SKY, 0], Some(GLOWSTONE), &registry);
+        terrain.set_voxel([14, SKY, 0], None, &registry);
+
+        for chunk in &terrain.chunks {
+            assert!(chunk.block_light.is_empty());
+        }
+    }
+
+    #[test]
+    fn breaking_one_light_keeps_the_other() {
+        let registry = registry();
+        let mut terrain = sky_terrain(&[[0, 0, 0], [1, 0, 0]]);
+        terrain.set_voxel([10, SKY, 0], Some(GLOWSTONE), &registry);
+        terrain.set_voxel([16, SKY, 0], Some(GLOWSTONE), &registry);
+        terrain.set_voxel([10, SKY, 0], None, &registry);
+
+        let mut expected = sky_terrain(&[[0, 0, 0], [1, 0, 0]]);
+        expected.set_voxel([16, SKY, 0], Some(GLOWSTONE), &registry);
+        for (chunk, expected) in terrain.chunks.iter().zip(&expected.chunks) {
+            assert_eq!(chunk.block_light, expected.block_light);
+        }
+    }
+
+    #[test]
+    fn covering_a_lit_voxel_shadows_behind_it() {
+        let registry = registry();
+        let mut terrain = sky_terrain(&[[0, 0, 0]]);
+        terrain.set_voxel([0, SKY, 0], Some(GLOWSTONE), &registry);
+        terrain.set_voxel([0, SKY, 1], Some(STONE), &registry);
+
+        assert_eq!(terrain.block_light([0, SKY, 1]), 0);
+        // The light now has to go around the stone.
+        assert_eq!(terrain.block_light([0, SKY, 2]), MAX_LIGHT - 4);
+    }
+
+    #[test]
+    fn skylight_comes_in_from_beyond_the_padding() {
+        // A shaft open to the sky in the next chunk along x, four voxels past the padding, lights
+        // a roofed tunnel running back into this one.
+        let registry = registry();
+        let mut terrain = sky_terrain(&[[0, 0, 0], [1, 0, 0]]);
+        terrain.set_voxel([-1, SKY, 0], Some(STONE), &registry);
+        for x in 0..20 {
+            terrain.set_voxel([x, SKY + 1, 0], Some(STONE), &registry);
+            terrain.set_voxel([x, SKY, -1], Some(STONE), &registry);
+            terrain.set_voxel([x, SKY, 1], Some(STONE), &registry);
+        }
+
+        assert_eq!(terrain.skylight([20, SKY, 0]), MAX_LIGHT);
+        assert_eq!(terrain.skylight([16, SKY, 0]), MAX_LIGHT - 4);
+        assert_eq!(terrain.skylight([15, SKY, 0]), MAX_LIGHT - 5);
+        assert_eq!(terrain.skylight([0, SKY, 0]), 0);
+        let padding = terrain
+            .chunk([0, SKY_CHUNK, 0])
+            .unwrap()
+            .skylight
+            .get([16, 0, 0]);
+        assert_eq!(padding, MAX_LIGHT - 4);
+
+        // Opening the roof lets the sky straight in, and closing it again takes it away.
+        terrain.set_voxel([5, SKY + 1, 0], None, &registry);
+        assert_eq!(terrain.skylight([5, SKY, 0]), MAX_LIGHT);
+        assert_eq!(terrain.skylight([2, SKY, 0]), MAX_LIGHT - 3);
+        terrain.set_voxel([5, SKY + 1, 0], Some(STONE), &registry);
+        assert_eq!(terrain.skylight([5, SKY, 0]), 0);
+        assert_eq!(terrain.skylight([15, SKY, 0]), MAX_LIGHT - 5);
+    }
+
+    #[test]
+    fn digging_under_an_unloaded_surface_keeps_its_column_top() {
+        let registry = registry();
+        let noise = Arc::new(TerrainNoise::new(WORLD_SEED, &TerrainParams::default()));
+        let surface = surface_heights(&noise, 0, 0, 1, 1)[0];
+        let (top, _) = Chunk::voxel_coords([0, surface, 0]);
+        let below = [top[0], top[1] - 1, top[2]];
+
+        let mut terrain = Terrain::new(noise.clone());
+        terrain.insert_generated(
+            terrain
+                .neighborhood([below[0], below[1], below[2]])
+                .generate(&noise),
+            &registry,
+        );
+        let dug = [0, below[1] * CHUNK_SIZE as isize, 0];
+        terrain.set_voxel(dug, None, &registry);
+        terrain.insert_generated(
+            terrain
+                .neighborhood([top[0], top[1], top[2]])
+                .generate(&noise),
+            &registry,
+        );
+
+        for coords in [below, top] {
+            let chunk = terrain.chunk(coords).unwrap();
+            assert_eq!(chunk.column_tops[Chunk::column_index(0, 0)], surface);
+        }
+        assert_eq!(terrain.skylight(dug), 0);
+    }
+
+    #[test]
+    fn light_does_not_depend_on_load_order() {
+        let registry = registry();
+        let noise = Arc::new(TerrainNoise::new(WORLD_SEED, &TerrainParams::default()));
+        // A glowstone resting on the surface at the edge of A, lighting the top of B.
+        let surface = surface_heights(&noise, 15, 0, 1, 1)[0];
+        let light_on_border = [15, surface + 1, 0];
+        let (a, _) = Chunk::voxel_coords(light_on_border);
+        let b = [a[0] + 1, a[1], a[2]];
+
+        let mut a_first = Terrain::new(noise.clone());
+        a_first.insert_generated(
+            a_first.neighborhood([a[0], a[1], a[2]]).generate(&noise),
+            &registry,
+        );
+        a_first.set_voxel(light_on_border, Some(GLOWSTONE), &registry);
+        a_first.insert_generated(
+            a_first.neighborhood([b[0], b[1], b[2]]).generate(&noise),
+            &registry,
+        );
+
+        let mut b_first = Terrain::new(noise.clone());
+        b_first.insert_generated(
+            b_first.neighborhood([b[0], b[1], b[2]]).generate(&noise),
+            &registry,
+        );
+        b_first.insert_generated(
+            b_first.neighborhood([a[0], a[1], a[2]]).generate(&noise),
+            &registry,
+        );
+        b_first.set_voxel(light_on_border, Some(GLOWSTONE), &registry);
+
+        let settings = MeshingSettings::default();
+        for coords in [a, b] {
+            let [first, second] = [&a_first, &b_first].map(|terrain| {
+                let chunk = terrain.chunk(coords).unwrap();
+                let light = chunk.light(&settings);
+                let mesh = chunk.to_mesh(&settings, MeshPass::Opaque, &registry, &light);
+                let bytes = [Mesh::ATTRIBUTE_POSITION, Mesh::ATTRIBUTE_COLOR]
+                    .map(|attribute| mesh.attribute(attribute).unwrap().get_bytes().to_vec());
+                (chunk.column_tops.clone(), bytes)
+            });
+            assert!(first == second, "chunk {coords:?} depends on load order");
         }
     }
 }
@@ -0,0 +1,46 @@
+//! Fixtures shared by the test modules.
+
+use std::sync::Arc;
+
+use crate::{
+    block::{BlockId, BlockRegistry},
+    chunk::{Chunk, CHUNK_SIZE},
+    generation::{TerrainNoise, TerrainParams},
+    terrain::{Terrain, WORLD_SEED},
+    voxel::Voxel,
+};
+
+pub const STONE: Voxel = Voxel {
+    block: BlockId::STONE,
+    level: 0,
+};
+pub const GLASS: Voxel = Voxel {
+    block: BlockId::GLASS,
+    level: 0,
+};
+pub const GLOWSTONE: Voxel = Voxel {
+    block: BlockId::GLOWSTONE,
+    level: 0,
+};
+
+/// Chunk Y and world Y of chunks far enough above the terrain to hold only air.
+pub const SKY_CHUNK: isize = 10;
+pub const SKY: isize = SKY_CHUNK * CHUNK_SIZE as isize;
+
+pub fn registry() -> BlockRegistry {
+    BlockRegistry::bundled()
+}
+
+/// Air-filled chunks at the chunk coordinates given, with Y counted up from `SKY_CHUNK`.
+pub fn sky_terrain(chunks: &[[isize; 3]]) -> Terrain {
+    let params = TerrainParams {
+        water_level: None,
+        ..Default::default()
+    };
+    let noise = Arc::new(TerrainNoise::new(WORLD_SEED, &params));
+    let mut terrain = Terrain::new(noise.clone());
+    for &[x, y, z] in chunks {
+        terrain.insert(Chunk::new(&noise, x, SKY_CHUNK + y, z));
+    }
+    terrain
+}
@@ -1,14 +1,149 @@
+use crate::block::BlockId;
+
 pub const VOXEL_SIZE: f32 = 1.0;
+pub const LIQUID_LEVEL_FULL: u8 = 7;
 
-#[derive(Clone, Copy, Debug, Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
 pub struct Voxel {
-    pub ty: VoxelType,
+    pub block: BlockId,
+    /// Liquid fill in eighths above the first, so `LIQUID_LEVEL_FULL` is a whole voxel. Solid
+    /// blocks ignore it.
+    pub level: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Face {
+    Left,
+    Right,
+    Bottom,
+    Top,
+    Back,
+    Front,
 }
 
-#[derive(Clone, Copy, Debug, Default)]
-pub enum VoxelType {
-    #[default]
-    Stone,
-    Dirt,
-    Grass,
+impl Face {
+    pub const ALL: [Face; 6] = [
+        Face::Left,
+        Face::Right,
+        Face::Bottom,
+        Face::Top,
+        Face::Back,
+        Face::Front,
+    ];
+
+    pub fn axis(self) -> usize {
+        match self {
+            Face::Left | Face::Right => 0,
+            Face::Bottom | Face::Top => 1,
+            Face::Back | Face::Front => 2,
+        }
+    }
+
+    pub fn sign(self) -> isize {
+        match self {
+            Face::Left | Face::Bottom | Face::Back => -1,
+            Face::Right | Face::Top | Face::Front => 1,
+        }
+    }
+
+    pub fn opposite(self) -> Face {
+        match self {
+            Face::Left => Face::Right,
+            Face::Right => Face::Left,
+            Face::Bottom => Face::Top,
+            Face::Top => Face::Bottom,
+            Face::Back => Face::Front,
+            Face::Front => Face::Back,
+        }
+    }
+
+    pub fn offset(self) -> [isize; 3] {
+        let mut offset = [0; 3];
+        offset[self.axis()] = self.sign();
+        offset
+    }
+
+    pub fn normal(self) -> [f32; 3] {
+        self.offset().map(|o| o as f32)
+    }
+
+    pub fn from_normal(normal: [f32; 3]) -> Option<Face> {
+        Face::ALL.into_iter().find(|face| face.normal() == normal)
+    }
+
+    pub fn positions(self, [x, y, z]: [isize; 3]) -> [[f32; 3]; 4] {
+        let neg_x = x as f32 - VOXEL_SIZE * 0.5;
+        let pos_x = x as f32 + VOXEL_SIZE * 0.5;
+        let neg_y = y as f32 - VOXEL_SIZE * 0.5;
+        let pos_y = y as f32 + VOXEL_SIZE * 0.5;
+        let neg_z = z as f32 - VOXEL_SIZE * 0.5;
+        let pos_z = z as f32 + VOXEL_SIZE * 0.5;
+
+        match self {
+            Face::Left => [
+                [neg_x, neg_y, neg_z],
+                [neg_x, neg_y, pos_z],
+                [neg_x, pos_y, pos_z],
+                [neg_x, pos_y, neg_z],
+            ],
+            Face::Right => [
+                [pos_x, neg_y, pos_z],
+                [pos_x, neg_y, neg_z],
+                [pos_x, pos_y, neg_z],
+                [pos_x, pos_y, pos_z],
+            ],
+            Face::Bottom => [
+                [neg_x, neg_y, neg_z],
+                [pos_x, neg_y, neg_z],
+                [pos_x, neg_y, pos_z],
+                [neg_x, neg_y, pos_z],
+            ],
+            Face::Top => [
+                [neg_x, pos_y, pos_z],
+                [pos_x, pos_y, pos_z],
+                [pos_x, pos_y, neg_z],
+                [neg_x, pos_y, neg_z],
+            ],
+            Face::Back => [
+                [pos_x, neg_y, neg_z],
+                [neg_x, neg_y, neg_z],
+                [neg_x, pos_y, neg_z],
+                [pos_x, pos_y, neg_z],
+            ],
+            Face::Front => [
+                [neg_x, neg_y, pos_z],
+                [pos_x, neg_y, pos_z],
+                [pos_x, pos_y, pos_z],
+                [neg_x, pos_y, pos_z],
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opposite_faces_point_the_other_way() {
+        for face in Face::ALL {
+            let opposite = face.opposite();
+            assert_ne!(opposite, face);
+            assert_eq!(opposite.axis(), face.axis());
+            assert_eq!(opposite.offset(), face.offset().map(|o| -o));
+            assert_eq!(opposite.opposite(), face);
+        }
+    }
+
+    #[test]
+    fn from_normal_finds_each_axis() {
+        assert_eq!(Face::from_normal([-1.0, 0.0, 0.0]), Some(Face::Left));
+        assert_eq!(Face::from_normal([1.0, 0.0, 0.0]), Some(Face::Right));
+        assert_eq!(Face::from_normal([0.0, -1.0, 0.0]), Some(Face::Bottom));
+        assert_eq!(Face::from_normal([0.0, 1.0, 0.0]), Some(Face::Top));
+        assert_eq!(Face::from_normal([0.0, 0.0, -1.0]), Some(Face::Back));
+        assert_eq!(Face::from_normal([0.0, 0.0, 1.0]), Some(Face::Front));
+        assert_eq!(Face::from_normal([0.0; 3]), None);
+        assert_eq!(Face::from_normal([1.0, 1.0, 0.0]), None);
+    }
 }